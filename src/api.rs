@@ -2,14 +2,15 @@ use ic_cdk_macros::*;
 use candid::Principal;
 use ic_cdk::api::caller;
 use crate::domain::*;
-use crate::services::{EstimationService, EscrowService, SettlementService, BalanceService, SubscriptionService, PaymentService};
+use crate::services::{EstimationService, EscrowService, SettlementService, BalanceService, SubscriptionService, PaymentService, JournalService, TreasuryService, RegistryService, DisputeService, SweeperService, BillingService};
 use crate::services as svc;
-use crate::services::{subscription, payment};
-use ic_cdk::api::time;
-use crate::infra::{Guards, Metrics};
+use crate::services::{balance, subscription, payment, treasury};
+use crate::infra::{Guards, Metrics, LedgerClient, ReceiptSignature};
+use crate::infra::ledger;
 
-#[query]
+#[update]
 fn estimate(job_spec: JobSpec) -> Result<CostQuote, String> {
+    Guards::require_caller_authenticated()?;
     Guards::validate_job_spec(&job_spec)?;
     let quote = EstimationService::estimate_cost(job_spec)?;
     Metrics::increment_counter("estimates_requested_total");
@@ -17,15 +18,42 @@ fn estimate(job_spec: JobSpec) -> Result<CostQuote, String> {
 }
 
 #[update]
-async fn escrow(job_id: String, amount: u64) -> Result<String, String> {
+async fn escrow(job_id: String, amount: u64, funding: Option<EscrowFunding>, ttl_ns: Option<u64>) -> Result<String, String> {
     Guards::require_caller_authenticated()?;
     Guards::validate_amount(amount)?;
 
-    let escrow_id = EscrowService::create_escrow(job_id, amount).await?;
+    let funding = funding.unwrap_or(EscrowFunding::Balance);
+    let escrow_id = EscrowService::create_escrow(job_id, amount, funding, ttl_ns).await?;
     Metrics::increment_counter("escrows_created_total");
     Ok(escrow_id)
 }
 
+#[update]
+async fn escrow_for_quote(
+    quote_id: String,
+    buffer_bps: Option<u32>,
+    funding: Option<EscrowFunding>,
+    ttl_ns: Option<u64>,
+) -> Result<String, String> {
+    Guards::require_caller_authenticated()?;
+
+    let funding = funding.unwrap_or(EscrowFunding::Balance);
+    let escrow_id = EscrowService::create_escrow_for_quote(quote_id, buffer_bps, funding, ttl_ns).await?;
+    Metrics::increment_counter("escrows_created_total");
+    Ok(escrow_id)
+}
+
+#[update]
+fn extend_escrow(escrow_id: String, extension_ns: u64) -> Result<u64, String> {
+    Guards::require_caller_authenticated()?;
+    EscrowService::extend_escrow(escrow_id, extension_ns)
+}
+
+#[query]
+fn get_quote(quote_id: String) -> Result<StoredQuote, String> {
+    EstimationService::get_quote(&quote_id)
+}
+
 #[update]
 async fn settle(receipt: Receipt) -> Result<String, String> {
     Guards::require_caller_authenticated()?;
@@ -54,6 +82,33 @@ fn update_policy(new_policy: FeePolicy) -> Result<(), String> {
     BalanceService::update_fee_policy(new_policy)
 }
 
+#[query]
+fn policy_version() -> u32 {
+    BalanceService::get_fee_policy_version()
+}
+
+#[query]
+fn policy_at(version: u32) -> Option<FeePolicy> {
+    BalanceService::get_fee_policy_at(version)
+}
+
+#[query]
+fn get_fee_tolerance() -> FeeTolerance {
+    BalanceService::get_fee_tolerance()
+}
+
+#[update]
+fn set_fee_tolerance(tolerance: FeeTolerance) -> Result<(), String> {
+    Guards::require_admin()?;
+    BalanceService::set_fee_tolerance(tolerance)
+}
+
+#[query]
+fn list_flagged_receipts(limit: Option<u32>) -> Result<Vec<Receipt>, String> {
+    Guards::require_admin()?;
+    Ok(SettlementService::list_flagged_receipts(limit.unwrap_or(100)))
+}
+
 // Admin role APIs
 #[query]
 fn is_admin() -> bool {
@@ -107,16 +162,16 @@ async fn get_or_create_free_basic_subscription() -> Result<Subscription, String>
     SubscriptionService::get_or_create_free_basic_subscription(pid).await
 }
 
+// Payment status follows from payments and billing; this is an admin override
 #[update]
-async fn update_payment_status(status: PaymentStatus) -> Result<(), String> {
-    Guards::require_caller_authenticated()?;
-    let pid = caller().to_text();
-    SubscriptionService::update_payment_status(pid, status).await
+async fn update_payment_status(user_principal: String, status: PaymentStatus) -> Result<(), String> {
+    Guards::require_admin()?;
+    SubscriptionService::update_payment_status(user_principal, status).await
 }
 
 #[update]
 async fn validate_agent_creation_quota(user_principal: String) -> Result<QuotaValidation, String> {
-    Guards::require_caller_authenticated()?;
+    Guards::require_self_or_coordinator(&user_principal)?;
     SubscriptionService::validate_agent_creation_quota(&user_principal).await
 }
 
@@ -129,10 +184,52 @@ async fn validate_quota() -> Result<QuotaValidation, String> {
 
 #[update]
 async fn validate_token_usage_quota(user_principal: String, tokens_requested: u64) -> Result<QuotaValidation, String> {
-    Guards::require_caller_authenticated()?;
+    Guards::require_self_or_coordinator(&user_principal)?;
     SubscriptionService::validate_token_usage_quota(&user_principal, tokens_requested).await
 }
 
+#[update]
+async fn record_inference(user_principal: String) -> Result<QuotaValidation, String> {
+    Guards::require_coordinator()?;
+    SubscriptionService::record_inference(&user_principal).await
+}
+
+#[update]
+async fn agent_started(user_principal: String, agent_id: String) -> Result<QuotaValidation, String> {
+    Guards::require_coordinator()?;
+    SubscriptionService::agent_started(&user_principal, agent_id).await
+}
+
+#[update]
+fn agent_stopped(user_principal: String, agent_id: String) -> Result<u32, String> {
+    Guards::require_coordinator()?;
+    SubscriptionService::agent_stopped(&user_principal, &agent_id)
+}
+
+#[update]
+async fn reserve_token_quota(user_principal: String, tokens: u64, ttl_ns: Option<u64>) -> Result<QuotaValidation, String> {
+    Guards::require_coordinator()?;
+    SubscriptionService::reserve_tokens(&user_principal, tokens, ttl_ns, caller().to_text()).await
+}
+
+#[update]
+fn commit_token_reservation(reservation_id: String, tokens_used: u64) -> Result<UsageMetrics, String> {
+    Guards::require_coordinator()?;
+    SubscriptionService::commit_reservation(&reservation_id, tokens_used, &caller().to_text())
+}
+
+#[update]
+fn release_token_reservation(reservation_id: String) -> Result<(), String> {
+    Guards::require_coordinator()?;
+    SubscriptionService::release_reservation(&reservation_id, &caller().to_text())
+}
+
+#[query]
+fn list_token_reservations(principal: Option<String>) -> Vec<QuotaReservation> {
+    let pid = principal.unwrap_or_else(|| caller().to_text());
+    SubscriptionService::list_reservations(&pid)
+}
+
 #[query]
 fn get_user_usage(principal: Option<String>) -> Option<UsageMetrics> {
     let pid = principal.unwrap_or_else(|| caller().to_text());
@@ -153,6 +250,32 @@ async fn renew_subscription() -> Result<(), String> {
     SubscriptionService::renew_subscription(pid).await
 }
 
+#[query]
+fn get_usage_history(principal: Option<String>, limit: Option<u32>) -> Vec<UsagePeriod> {
+    let pid = principal.unwrap_or_else(|| caller().to_text());
+    SubscriptionService::get_usage_history(&pid, limit.unwrap_or(12))
+}
+
+#[query]
+fn get_entitlements(principal: Option<String>) -> Option<Entitlements> {
+    let pid = principal.unwrap_or_else(|| caller().to_text());
+    SubscriptionService::get_entitlements(&pid)
+}
+
+#[query]
+fn quote_tier_change(new_tier: String) -> Result<TierChange, String> {
+    Guards::require_caller_authenticated()?;
+    let pid = caller().to_text();
+    SubscriptionService::quote_tier_change(&pid, &new_tier)
+}
+
+#[update]
+async fn change_subscription_tier(new_tier: String) -> Result<subscription::TierChangeOutcome, String> {
+    Guards::require_caller_authenticated()?;
+    let pid = caller().to_text();
+    SubscriptionService::change_subscription_tier(pid, new_tier).await
+}
+
 // Admin subscription APIs
 #[query]
 fn get_subscription_tiers() -> Vec<(String, TierConfig)> {
@@ -162,9 +285,40 @@ fn get_subscription_tiers() -> Vec<(String, TierConfig)> {
     for (key, value) in configs {
         result.push((key, value));
     }
+    result.sort_by(|a, b| a.0.cmp(&b.0));
     result
 }
 
+#[query]
+fn list_tier_catalog() -> Result<Vec<(String, TierConfig)>, String> {
+    Guards::require_admin()?;
+    Ok(SubscriptionService::list_tier_catalog())
+}
+
+#[query]
+fn get_tier_versions(tier_id: String) -> Result<Vec<TierConfig>, String> {
+    Guards::require_admin()?;
+    Ok(SubscriptionService::get_tier_versions(&tier_id))
+}
+
+#[update]
+fn create_tier(tier_id: String, config: TierConfig) -> Result<TierConfig, String> {
+    Guards::require_admin()?;
+    SubscriptionService::create_tier(tier_id, config)
+}
+
+#[update]
+fn update_tier(tier_id: String, config: TierConfig) -> Result<TierConfig, String> {
+    Guards::require_admin()?;
+    SubscriptionService::update_tier(tier_id, config)
+}
+
+#[update]
+fn retire_tier(tier_id: String) -> Result<TierConfig, String> {
+    Guards::require_admin()?;
+    SubscriptionService::retire_tier(tier_id)
+}
+
 #[query]
 fn list_all_subscriptions() -> Vec<Subscription> {
     Guards::require_admin().unwrap_or_default();
@@ -209,17 +363,271 @@ fn refund_escrow(escrow_id: String) -> Result<(), String> {
 }
 
 #[update]
-fn deposit(amount: u64) -> Result<(), String> {
+fn close_escrow(escrow_id: String) -> Result<u64, String> {
+    Guards::require_caller_authenticated()?;
+    EscrowService::close_escrow(escrow_id)
+}
+
+#[query]
+fn get_deposit_account() -> Result<ledger::Account, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(LedgerClient::deposit_account(&caller()))
+}
+
+#[query]
+fn get_deposit_account_identifier() -> Result<String, String> {
+    Guards::require_caller_authenticated()?;
+    let account = LedgerClient::deposit_account(&caller());
+    Ok(payment::AccountIdentifier::from_account(&account)?.to_hex())
+}
+
+#[update]
+async fn notify_deposit() -> Result<u64, String> {
+    Guards::require_caller_authenticated()?;
+    BalanceService::sync_deposit(caller()).await
+}
+
+#[update]
+async fn sync_deposit(principal_text: String) -> Result<u64, String> {
+    Guards::require_caller_authenticated()?;
+    let principal = Principal::from_text(&principal_text)
+        .map_err(|e| format!("Invalid principal: {}", e))?;
+    BalanceService::sync_deposit(principal).await
+}
+
+#[update]
+fn set_ledger_canister(principal_text: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    Principal::from_text(&principal_text).map_err(|e| format!("Invalid principal: {}", e))?;
+    svc::set_ledger_canister(principal_text);
+    Ok(())
+}
+
+#[update]
+async fn withdraw(amount: u64, to: Option<ledger::Account>) -> Result<u64, String> {
     Guards::require_caller_authenticated()?;
     Guards::validate_amount(amount)?;
-    BalanceService::deposit(caller().to_text(), amount)
+    let to = to.unwrap_or(ledger::Account { owner: caller(), subaccount: None });
+    BalanceService::withdraw(caller().to_text(), amount, to).await
+}
+
+#[query]
+fn list_pending_transfers(principal_id: Option<String>) -> Result<Vec<balance::PendingTransfer>, String> {
+    Guards::require_admin()?;
+    Ok(BalanceService::list_pending_transfers(principal_id))
 }
 
 #[update]
-fn withdraw(amount: u64) -> Result<(), String> {
+fn resolve_pending_transfer(reference: String, block_index: Option<u64>) -> Result<(), String> {
+    Guards::require_admin()?;
+    BalanceService::resolve_pending_transfer(reference, block_index)
+}
+
+#[query]
+fn list_pending_pulls(principal_id: Option<String>) -> Result<Vec<balance::PendingPull>, String> {
+    Guards::require_admin()?;
+    Ok(BalanceService::list_pending_pulls(principal_id))
+}
+
+#[update]
+fn resolve_pending_pull(reference: String, block_index: Option<u64>) -> Result<(), String> {
+    Guards::require_admin()?;
+    BalanceService::resolve_pending_pull(reference, block_index)
+}
+
+// Journal API
+#[query]
+fn get_journal_entries(principal_id: Option<String>, limit: Option<u32>) -> Result<Vec<JournalEntry>, String> {
     Guards::require_caller_authenticated()?;
+    let pid = principal_id.unwrap_or_else(|| caller().to_text());
+    let max_limit = limit.unwrap_or(50).min(200);
+    Ok(JournalService::entries_for_principal(&pid, max_limit))
+}
+
+#[query]
+fn reconcile_journal() -> Result<JournalReconciliation, String> {
+    Guards::require_admin()?;
+    Ok(JournalService::reconcile())
+}
+
+// Treasury API
+#[query]
+fn get_treasury_balance() -> TreasuryBalance {
+    TreasuryService::get_treasury_balance()
+}
+
+#[query]
+fn get_fee_sinks() -> Vec<FeeSink> {
+    TreasuryService::get_fee_sinks()
+}
+
+#[update]
+fn set_fee_sinks(sinks: Vec<FeeSink>) -> Result<(), String> {
+    Guards::require_admin()?;
+    TreasuryService::set_fee_sinks(sinks)
+}
+
+#[query]
+fn get_treasury_account() -> treasury::TreasuryAccountInfo {
+    TreasuryService::get_treasury_account()
+}
+
+#[update]
+fn set_treasury_account(owner: Principal, subaccount: Option<Vec<u8>>) -> Result<(), String> {
+    Guards::require_admin()?;
+    TreasuryService::set_treasury_account(owner, subaccount)
+}
+
+#[update]
+fn withdraw_treasury(fee_sink: Option<String>, amount: u64, recipient: Principal) -> Result<u64, String> {
+    Guards::require_admin()?;
     Guards::validate_amount(amount)?;
-    BalanceService::withdraw(caller().to_text(), amount)
+    TreasuryService::withdraw(fee_sink, amount, recipient)
+}
+
+// Settler and agent registry API
+#[update]
+fn register_settler(principal: Principal, role: SettlerRole) -> Result<(), String> {
+    Guards::require_admin()?;
+    RegistryService::register_settler(principal, role)
+}
+
+#[update]
+fn remove_settler(principal_id: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    RegistryService::remove_settler(&principal_id)
+}
+
+#[query]
+fn list_settlers() -> Vec<Settler> {
+    RegistryService::list_settlers()
+}
+
+#[update]
+fn register_agent(agent_id: String, payout_principal: Principal) -> Result<(), String> {
+    Guards::require_admin()?;
+    RegistryService::register_agent(agent_id, payout_principal)
+}
+
+#[update]
+fn set_agent_key(agent_id: String, public_key: Vec<u8>) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    RegistryService::set_agent_key(agent_id, public_key)
+}
+
+#[query]
+fn receipt_signing_payload(receipt: Receipt) -> Result<Vec<u8>, String> {
+    ReceiptSignature::signing_payload(&receipt)
+}
+
+#[update]
+fn remove_agent(agent_id: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    RegistryService::remove_agent(&agent_id)
+}
+
+#[query]
+fn get_agent(agent_id: String) -> Result<AgentRegistration, String> {
+    RegistryService::get_agent(&agent_id)
+}
+
+#[query]
+fn list_agents() -> Vec<AgentRegistration> {
+    RegistryService::list_agents()
+}
+
+#[update]
+fn authorize_escrow_settler(escrow_id: String, settler: Principal) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    RegistryService::authorize_escrow_settler(escrow_id, settler)
+}
+
+// Dispute API
+#[update]
+fn open_dispute(receipt_id: String, reason: String) -> Result<(), String> {
+    Guards::require_caller_authenticated()?;
+    DisputeService::open_dispute(receipt_id, reason)
+}
+
+#[update]
+fn resolve_dispute(receipt_id: String, resolution: DisputeResolution) -> Result<(), String> {
+    Guards::require_arbiter()?;
+    DisputeService::resolve_dispute(receipt_id, resolution)
+}
+
+#[query]
+fn list_disputes(open_only: Option<bool>, limit: Option<u32>) -> Vec<Receipt> {
+    DisputeService::list_disputes(open_only.unwrap_or(true), limit.unwrap_or(100).min(500))
+}
+
+#[update]
+fn release_payouts(limit: Option<u32>) -> Result<u32, String> {
+    Guards::require_caller_authenticated()?;
+    Ok(DisputeService::release_matured_payouts(limit.unwrap_or(100).min(500)))
+}
+
+#[query]
+fn get_dispute_window() -> u64 {
+    DisputeService::get_dispute_window()
+}
+
+#[update]
+fn set_dispute_window(window_ns: u64) -> Result<(), String> {
+    Guards::require_admin()?;
+    DisputeService::set_dispute_window(window_ns)
+}
+
+#[query]
+fn list_arbiters() -> Vec<String> {
+    DisputeService::list_arbiters()
+}
+
+#[update]
+fn add_arbiter(principal: Principal) -> Result<(), String> {
+    Guards::require_admin()?;
+    DisputeService::add_arbiter(principal)
+}
+
+#[update]
+fn remove_arbiter(principal_id: String) -> Result<(), String> {
+    Guards::require_admin()?;
+    DisputeService::remove_arbiter(&principal_id);
+    Ok(())
+}
+
+// Escrow expiry sweeper API
+#[query]
+fn get_sweeper_status() -> Option<SweeperStatus> {
+    SweeperService::get_status()
+}
+
+#[update]
+fn run_escrow_sweep() -> Result<SweeperStatus, String> {
+    Guards::require_admin()?;
+    Ok(SweeperService::run(SweepTrigger::Manual))
+}
+
+// Recurring billing API
+#[query]
+fn get_billing_policy() -> BillingPolicy {
+    BillingService::get_billing_policy()
+}
+
+#[update]
+fn set_billing_policy(policy: BillingPolicy) -> Result<(), String> {
+    Guards::require_admin()?;
+    BillingService::set_billing_policy(policy)
+}
+
+#[query]
+fn get_billing_status() -> Option<BillingRunStatus> {
+    BillingService::get_status()
+}
+
+#[update]
+async fn run_billing() -> Result<BillingRunStatus, String> {
+    Guards::require_admin()?;
+    Ok(BillingService::run(SweepTrigger::Manual).await)
 }
 
 // Payment API
@@ -234,7 +642,7 @@ async fn create_payment_request(subscription_tier: String) -> Result<payment::Pa
 async fn process_subscription_payment(payment_request: payment::PaymentRequest) -> Result<payment::PaymentTransaction, String> {
     Guards::require_caller_authenticated()?;
     let from_principal = caller();
-    PaymentService::process_icp_payment(payment_request, from_principal).await
+    PaymentService::process_subscription_payment(payment_request, from_principal).await
 }
 
 #[update]
@@ -282,7 +690,7 @@ fn list_all_payment_transactions(limit: Option<u32>) -> Result<Vec<payment::Paym
         state.payment_transactions.as_ref()
             .map(|txs| {
                 let mut transactions: Vec<payment::PaymentTransaction> = txs.values().cloned().collect();
-                transactions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                transactions.sort_by_key(|tx| std::cmp::Reverse(tx.created_at));
                 transactions.into_iter().take(max_limit as usize).collect()
             })
             .unwrap_or_default()
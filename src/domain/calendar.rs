@@ -0,0 +1,149 @@
+//! Calendar-month arithmetic on nanosecond UTC timestamps.
+//!
+//! Billing periods run from an anchor timestamp in whole calendar months, keeping the anchor's
+//! time of day. When the anchor's day does not exist in a month (the 31st in April, the 29th in a
+//! common-year February) the period boundary falls on that month's last day instead, and later
+//! months return to the anchor day. Date conversion uses the proleptic Gregorian algorithms from
+//! Howard Hinnant's `chrono`-compatible date library.
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Timestamp `months` calendar months after `anchor`, clamped to the end of shorter months
+pub fn add_months(anchor: u64, months: u32) -> u64 {
+    let days = (anchor / NANOS_PER_DAY) as i64;
+    let time_of_day = anchor % NANOS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+
+    let month_index = (year * 12 + (month as i64 - 1)) + months as i64;
+    let target_year = month_index.div_euclid(12);
+    let target_month = (month_index.rem_euclid(12) + 1) as u32;
+    let target_day = day.min(days_in_month(target_year, target_month));
+
+    let target_days = days_from_civil(target_year, target_month, target_day);
+    (target_days as u64).saturating_mul(NANOS_PER_DAY).saturating_add(time_of_day)
+}
+
+/// Start and end of the anchored monthly period containing `at`. Timestamps before the anchor
+/// fall in the first period.
+pub fn period_bounds(anchor: u64, at: u64) -> (u64, u64) {
+    if at < anchor {
+        return (anchor, add_months(anchor, 1));
+    }
+
+    let (anchor_year, anchor_month, _) = civil_from_days((anchor / NANOS_PER_DAY) as i64);
+    let (at_year, at_month, _) = civil_from_days((at / NANOS_PER_DAY) as i64);
+    let mut months = ((at_year - anchor_year) * 12 + at_month as i64 - anchor_month as i64).max(0) as u32;
+
+    // The month difference is exact or one too many when `at` precedes the anchor day
+    if add_months(anchor, months) > at {
+        months = months.saturating_sub(1);
+    }
+    (add_months(anchor, months), add_months(anchor, months + 1))
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        _ if is_leap_year(year) => 29,
+        _ => 28,
+    }
+}
+
+/// Days since 1970-01-01 for a civil date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Civil date for a count of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+    fn at(year: i64, month: u32, day: u32) -> u64 {
+        days_from_civil(year, month, day) as u64 * NANOS_PER_DAY
+    }
+
+    #[test]
+    fn civil_conversion_round_trips() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(civil_from_days(days_from_civil(2100, 3, 1)), (2100, 3, 1));
+    }
+
+    #[test]
+    fn month_end_clamps_in_leap_and_common_years() {
+        assert_eq!(add_months(at(2024, 1, 31), 1), at(2024, 2, 29));
+        assert_eq!(add_months(at(2023, 1, 31), 1), at(2023, 2, 28));
+        assert_eq!(add_months(at(2100, 1, 31), 1), at(2100, 2, 28));
+        assert_eq!(add_months(at(2000, 1, 31), 1), at(2000, 2, 29));
+    }
+
+    #[test]
+    fn later_months_return_to_the_anchor_day() {
+        let anchor = at(2024, 1, 31);
+        assert_eq!(add_months(anchor, 2), at(2024, 3, 31));
+        assert_eq!(add_months(anchor, 3), at(2024, 4, 30));
+        assert_eq!(add_months(anchor, 4), at(2024, 5, 31));
+    }
+
+    #[test]
+    fn months_roll_over_into_the_next_year() {
+        assert_eq!(add_months(at(2024, 12, 15), 1), at(2025, 1, 15));
+        assert_eq!(add_months(at(2024, 11, 30), 3), at(2025, 2, 28));
+        assert_eq!(add_months(at(2024, 3, 1), 12), at(2025, 3, 1));
+    }
+
+    #[test]
+    fn time_of_day_is_kept() {
+        assert_eq!(add_months(at(2024, 1, 31) + 5 * HOUR, 1), at(2024, 2, 29) + 5 * HOUR);
+    }
+
+    #[test]
+    fn period_bounds_follow_the_anchor() {
+        let anchor = at(2024, 1, 31) + 5 * HOUR;
+        assert_eq!(period_bounds(anchor, anchor - 1), (anchor, at(2024, 2, 29) + 5 * HOUR));
+        assert_eq!(period_bounds(anchor, anchor), (anchor, at(2024, 2, 29) + 5 * HOUR));
+        assert_eq!(
+            period_bounds(anchor, at(2024, 3, 31)),
+            (at(2024, 2, 29) + 5 * HOUR, at(2024, 3, 31) + 5 * HOUR),
+        );
+        assert_eq!(
+            period_bounds(anchor, at(2024, 3, 31) + 5 * HOUR),
+            (at(2024, 3, 31) + 5 * HOUR, at(2024, 4, 30) + 5 * HOUR),
+        );
+    }
+
+    #[test]
+    fn period_bounds_cross_the_year_end() {
+        let anchor = at(2024, 12, 20);
+        assert_eq!(period_bounds(anchor, at(2025, 1, 5)), (at(2024, 12, 20), at(2025, 1, 20)));
+        assert_eq!(period_bounds(anchor, at(2025, 1, 20)), (at(2025, 1, 20), at(2025, 2, 20)));
+        assert_eq!(period_bounds(anchor, at(2026, 1, 19)), (at(2025, 12, 20), at(2026, 1, 20)));
+    }
+}
@@ -2,6 +2,10 @@ use serde::{Deserialize, Serialize};
 use candid::CandidType;
 use std::collections::HashMap;
 
+pub mod money;
+pub mod calendar;
+pub use money::*;
+
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct JobSpec {
     pub job_id: String,
@@ -24,12 +28,22 @@ pub struct CostQuote {
     pub job_id: String,
     pub estimated_cost: u64,
     pub base_cost: u64,
-    pub priority_multiplier: f32,
+    pub priority_multiplier_bps: u32,
     pub protocol_fee: u64,
     pub quote_expires_at: u64,
     pub quote_id: String,
 }
 
+/// A quote as issued by `estimate`, kept until an escrow is opened against it or it expires
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct StoredQuote {
+    pub quote: CostQuote,
+    pub principal_id: String,
+    pub fee_policy_version: u32,
+    // Escrow opened for this quote; a quote can back only one escrow
+    pub escrow_id: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct EscrowAccount {
     pub escrow_id: String,
@@ -39,6 +53,41 @@ pub struct EscrowAccount {
     pub status: EscrowStatus,
     pub created_at: u64,
     pub expires_at: u64,
+    // Final accounting; optional so escrows stored before these fields still decode
+    pub paid_amount: Option<u64>,
+    pub refunded_amount: Option<u64>,
+    // Multi-stage settlement: funds still locked and the settlements drawn so far
+    pub remaining_amount: Option<u64>,
+    pub settlement_ids: Option<Vec<String>>,
+    // Fee policy version in force when the escrow was opened; settlement fees are checked against it
+    pub fee_policy_version: Option<u32>,
+    // Quote this escrow was opened for, if any
+    pub quote_id: Option<String>,
+    // Settlers the holder allowed to settle this escrow's job; None leaves it to registered coordinators
+    pub settlers: Option<Vec<String>>,
+}
+
+impl EscrowAccount {
+    pub fn total_paid(&self) -> u64 {
+        self.paid_amount.unwrap_or(0)
+    }
+
+    pub fn total_refunded(&self) -> u64 {
+        self.refunded_amount.unwrap_or(0)
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.remaining_amount.unwrap_or_else(|| {
+            self.amount.saturating_sub(self.total_paid().saturating_add(self.total_refunded()))
+        })
+    }
+}
+
+/// Where escrowed funds come from: the econ balance, or an ICRC-2 allowance pulled on the spot
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum EscrowFunding {
+    Balance,
+    Allowance,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -61,6 +110,93 @@ pub struct Receipt {
     pub settlement_status: SettlementStatus,
     pub created_at: u64,
     pub settled_at: Option<u64>,
+    // Filled in by settlement: where every e8s of the escrow went
+    pub lines: Option<Vec<ReceiptLine>>,
+    // Close the escrow after this receipt (default); Some(false) settles one stage and keeps it open
+    pub close_escrow: Option<bool>,
+    // Set by settlement when the fees deviated from the escrow's fee policy but were let through
+    pub fee_deviation: Option<FeeDeviation>,
+    // Agent's Ed25519 signature over the receipt's canonical signing payload
+    pub signature: Option<Vec<u8>>,
+    // Agent payout held until the dispute window closes
+    pub payout_hold: Option<PayoutHold>,
+    pub dispute: Option<Dispute>,
+    // Every settlement status change, oldest first
+    pub status_history: Option<Vec<SettlementTransition>>,
+    // Multi-agent jobs: how the agent payout is split; None pays it all to `agent_id`
+    pub shares: Option<Vec<PayoutShare>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum ShareSpec {
+    /// Exact e8s amount
+    Amount(u64),
+    /// Relative weight; the payout is divided in proportion to all weights
+    Weight(u32),
+}
+
+/// One agent's part of a multi-agent payout
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PayoutShare {
+    pub agent_id: String,
+    pub share: ShareSpec,
+}
+
+/// Agent payout parked in the journal's PayoutHold account while the payer may still dispute it
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PayoutHold {
+    pub amount: u64,
+    pub payee: String,
+    pub release_at: u64,
+    pub released_at: Option<u64>,
+    // Per-principal split of `amount` for multi-agent receipts
+    pub shares: Option<Vec<(String, u64)>>,
+}
+
+impl PayoutHold {
+    /// Who receives the held amount, and how much each
+    pub fn distribution(&self) -> Vec<(String, u64)> {
+        self.shares.clone().unwrap_or_else(|| vec![(self.payee.clone(), self.amount)])
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum DisputeResolution {
+    FullPayout,
+    PartialRefund { refund_amount: u64 },
+    FullRefund,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Dispute {
+    pub opened_by: String,
+    pub reason: String,
+    pub opened_at: u64,
+    pub resolution: Option<DisputeResolution>,
+    pub resolved_by: Option<String>,
+    pub resolved_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SettlementTransition {
+    pub status: SettlementStatus,
+    pub changed_by: String,
+    pub changed_at: u64,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum ReceiptLineKind {
+    AgentPayout,
+    ProtocolFee,
+    Refund,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ReceiptLine {
+    pub kind: ReceiptLineKind,
+    pub recipient: String,
+    pub amount: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -77,35 +213,216 @@ pub enum SettlementStatus {
     Completed,
     Failed,
     Disputed,
+    // Dispute resolved by returning the whole agent payout to the payer
+    Refunded,
 }
 
+/// Fee schedule; every rate is in basis points (10_000 = 100%, or 1.0x for multipliers)
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct FeePolicy {
-    pub protocol_fee_percentage: f32,
-    pub agent_fee_percentage: f32,
+    pub protocol_fee_bps: u32,
+    pub agent_fee_bps: u32,
     pub minimum_fee: u64,
-    pub priority_multipliers: HashMap<String, f32>,
+    pub priority_multipliers_bps: HashMap<String, u32>,
     pub last_updated: u64,
+    // Escrow lifetime limits in nanoseconds; optional so policies stored before them still decode
+    pub default_escrow_ttl: Option<u64>,
+    pub max_escrow_ttl: Option<u64>,
 }
 
 impl Default for FeePolicy {
     fn default() -> Self {
-        let mut priority_multipliers = HashMap::new();
-        priority_multipliers.insert("Low".to_string(), 0.8);
-        priority_multipliers.insert("Normal".to_string(), 1.0);
-        priority_multipliers.insert("High".to_string(), 1.5);
-        priority_multipliers.insert("Critical".to_string(), 2.0);
+        let mut priority_multipliers_bps = HashMap::new();
+        priority_multipliers_bps.insert("Low".to_string(), 8_000);
+        priority_multipliers_bps.insert("Normal".to_string(), 10_000);
+        priority_multipliers_bps.insert("High".to_string(), 15_000);
+        priority_multipliers_bps.insert("Critical".to_string(), 20_000);
 
         Self {
-            protocol_fee_percentage: 3.0,
-            agent_fee_percentage: 7.0,
+            protocol_fee_bps: 300,
+            agent_fee_bps: 700,
             minimum_fee: 1000, // 0.001 tokens
-            priority_multipliers,
+            priority_multipliers_bps,
             last_updated: 0,
+            default_escrow_ttl: Some(FeePolicy::DEFAULT_ESCROW_TTL),
+            max_escrow_ttl: Some(FeePolicy::MAX_ESCROW_TTL),
         }
     }
 }
 
+impl FeePolicy {
+    pub const DEFAULT_ESCROW_TTL: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours in nanoseconds
+    pub const MAX_ESCROW_TTL: u64 = 7 * 24 * 60 * 60 * 1_000_000_000; // 7 days
+
+    pub fn default_escrow_ttl(&self) -> u64 {
+        self.default_escrow_ttl.unwrap_or(Self::DEFAULT_ESCROW_TTL)
+    }
+
+    pub fn max_escrow_ttl(&self) -> u64 {
+        self.max_escrow_ttl.unwrap_or(Self::MAX_ESCROW_TTL)
+    }
+
+    pub fn protocol_fee_rate(&self) -> BasisPoints {
+        BasisPoints(self.protocol_fee_bps)
+    }
+
+    pub fn agent_fee_rate(&self) -> BasisPoints {
+        BasisPoints(self.agent_fee_bps)
+    }
+
+    /// Multiplier for a priority; 1.0x when the policy does not list it
+    pub fn priority_multiplier(&self, priority: &JobPriority) -> BasisPoints {
+        let priority_key = match priority {
+            JobPriority::Low => "Low",
+            JobPriority::Normal => "Normal",
+            JobPriority::High => "High",
+            JobPriority::Critical => "Critical",
+        };
+
+        self.priority_multipliers_bps
+            .get(priority_key)
+            .copied()
+            .map(BasisPoints)
+            .unwrap_or(BasisPoints::ONE_HUNDRED_PERCENT)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let total_fee_bps = self.protocol_fee_bps as u64 + self.agent_fee_bps as u64;
+        if total_fee_bps > BasisPoints::DENOMINATOR as u64 {
+            return Err("Protocol and agent fees cannot exceed 100%".to_string());
+        }
+        if let Some((name, _)) = self.priority_multipliers_bps.iter().find(|(_, bps)| **bps == 0) {
+            return Err(format!("Priority multiplier for {} must be non-zero", name));
+        }
+        if self.default_escrow_ttl() == 0 {
+            return Err("Default escrow TTL must be greater than zero".to_string());
+        }
+        if self.default_escrow_ttl() > self.max_escrow_ttl() {
+            return Err("Default escrow TTL cannot exceed the maximum".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Fee policy as stored before rates moved to basis points; only read while migrating stable state
+#[derive(Debug, Clone, Deserialize, CandidType)]
+pub struct LegacyFeePolicy {
+    pub protocol_fee_percentage: f32,
+    pub agent_fee_percentage: f32,
+    pub minimum_fee: u64,
+    pub priority_multipliers: HashMap<String, f32>,
+    pub last_updated: u64,
+}
+
+impl LegacyFeePolicy {
+    /// Round each percentage and multiplier to the nearest basis point
+    pub fn to_fee_policy(&self) -> FeePolicy {
+        let to_bps = |value: f64| (value.max(0.0).round() as u64).min(u32::MAX as u64) as u32;
+
+        FeePolicy {
+            protocol_fee_bps: to_bps(self.protocol_fee_percentage as f64 * 100.0),
+            agent_fee_bps: to_bps(self.agent_fee_percentage as f64 * 100.0),
+            minimum_fee: self.minimum_fee,
+            priority_multipliers_bps: self.priority_multipliers
+                .iter()
+                .map(|(name, multiplier)| (name.clone(), to_bps(*multiplier as f64 * 10_000.0)))
+                .collect(),
+            last_updated: self.last_updated,
+            default_escrow_ttl: None,
+            max_escrow_ttl: None,
+        }
+    }
+}
+
+/// What settlement does with a receipt whose fees fall outside the tolerance
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum FeeDeviationAction {
+    Reject,
+    Flag,
+}
+
+/// Allowed deviation of a receipt's protocol and agent fees from the recomputed ones
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct FeeTolerance {
+    pub tolerance_bps: u32,
+    pub action: FeeDeviationAction,
+}
+
+impl Default for FeeTolerance {
+    fn default() -> Self {
+        Self {
+            tolerance_bps: 100, // 1% of the expected fee
+            action: FeeDeviationAction::Reject,
+        }
+    }
+}
+
+/// Fees a flagged receipt should have carried under its escrow's policy version
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct FeeDeviation {
+    pub policy_version: u32,
+    pub expected: FeesBreakdown,
+}
+
+/// Additional destination for a share of the protocol fee; the treasury keeps the remainder
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct FeeSink {
+    pub name: String,
+    pub share_bps: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum SettlerRole {
+    /// Coordinator canister; may settle any escrow that has not restricted its settlers
+    Coordinator,
+    /// Agent principal; may settle only escrows that list it, for agents paying out to it
+    Agent,
+}
+
+/// Principal allowed to call `settle`
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Settler {
+    pub principal_id: String,
+    pub role: SettlerRole,
+    pub registered_at: u64,
+}
+
+/// Agent that receipts may name, with the principal its earnings are paid to
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct AgentRegistration {
+    pub agent_id: String,
+    pub payout_principal: String,
+    pub registered_at: u64,
+    // Ed25519 public key receipts paying this agent must be signed with
+    pub public_key: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum SweepTrigger {
+    Timer,
+    Manual,
+}
+
+/// Result of the most recent escrow expiry sweep, plus running totals
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct SweeperStatus {
+    pub last_run_at: u64,
+    pub last_trigger: SweepTrigger,
+    pub last_expired: u32,
+    pub last_failed: u32,
+    pub backlog: u32,
+    pub total_expired: u64,
+    pub runs: u64,
+    // Held payouts released because their dispute window closed
+    pub last_released_payouts: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TreasuryBalance {
+    pub protocol_treasury: u64,
+    pub fee_sinks: Vec<(String, u64)>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct Balance {
     pub principal_id: String,
@@ -115,6 +432,117 @@ pub struct Balance {
     pub last_updated: u64,
 }
 
+impl Balance {
+    pub fn empty(principal_id: &str, now: u64) -> Self {
+        Self {
+            principal_id: principal_id.to_string(),
+            available_balance: 0,
+            escrowed_balance: 0,
+            total_earnings: 0,
+            last_updated: now,
+        }
+    }
+}
+
+// Double-entry journal
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq, Eq, Hash)]
+pub enum JournalAccount {
+    /// Tokens held in custody on behalf of all users (asset side)
+    External,
+    UserAvailable(String),
+    UserEscrow(String),
+    AgentEarnings(String),
+    ProtocolTreasury,
+    FeeSink(String),
+    // Agent payout of one receipt (keyed by receipt_id) awaiting the end of its dispute window
+    PayoutHold(String),
+}
+
+impl JournalAccount {
+    /// External is the only asset account; every other account is a liability owed to someone
+    pub fn is_debit_normal(&self) -> bool {
+        matches!(self, JournalAccount::External)
+    }
+
+    pub fn principal(&self) -> Option<&str> {
+        match self {
+            JournalAccount::UserAvailable(p)
+            | JournalAccount::UserEscrow(p)
+            | JournalAccount::AgentEarnings(p) => Some(p),
+            JournalAccount::External
+            | JournalAccount::ProtocolTreasury
+            | JournalAccount::FeeSink(_)
+            | JournalAccount::PayoutHold(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum JournalEntryKind {
+    Opening,
+    Deposit,
+    Withdrawal,
+    WithdrawalReversal,
+    EscrowLock,
+    EscrowRelease,
+    EscrowRefund,
+    EscrowExpiry,
+    Fee,
+    TreasuryWithdrawal,
+    SubscriptionPayment,
+    PayoutRelease,
+    DisputeRefund,
+    OverageCharge,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum PostingSide {
+    Debit,
+    Credit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Posting {
+    pub account: JournalAccount,
+    pub side: PostingSide,
+    pub amount: u64,
+}
+
+impl Posting {
+    pub fn debit(account: JournalAccount, amount: u64) -> Self {
+        Self { account, side: PostingSide::Debit, amount }
+    }
+
+    pub fn credit(account: JournalAccount, amount: u64) -> Self {
+        Self { account, side: PostingSide::Credit, amount }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct JournalEntry {
+    pub entry_id: u64,
+    pub kind: JournalEntryKind,
+    pub reference: String,
+    pub postings: Vec<Posting>,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BalanceDiscrepancy {
+    pub principal_id: String,
+    pub recorded: Balance,
+    pub derived: Balance,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct JournalReconciliation {
+    pub conserved: bool,
+    pub total_assets: u64,
+    pub total_liabilities: u64,
+    pub entries_checked: u64,
+    pub discrepancies: Vec<BalanceDiscrepancy>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct EconHealth {
     pub total_escrows: u32,
@@ -124,6 +552,9 @@ pub struct EconHealth {
     pub total_volume: u64,
     pub protocol_fees_collected: u64,
     pub average_job_cost: f64,
+    pub open_disputes: u32,
+    pub resolved_disputes: u32,
+    pub held_payouts: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -146,6 +577,8 @@ pub enum SubscriptionTier {
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct Subscription {
     pub principal_id: String,
+    // Catalog key of the tier; `tier` is the version subscribed to, refreshed on renewal
+    pub tier_id: Option<String>,
     pub tier: TierConfig,
     pub started_at: u64,
     pub expires_at: u64,
@@ -154,6 +587,120 @@ pub struct Subscription {
     pub payment_status: PaymentStatus,
     pub created_at: u64,
     pub updated_at: u64,
+    // Downgrade waiting for the end of the current period
+    pub pending_change: Option<TierChange>,
+    // Renewal attempts for the current period; cleared once a renewal succeeds
+    pub billing: Option<BillingState>,
+    // Agents currently running for this subscriber, as reported by the agent factory
+    pub active_agents: Option<Vec<String>>,
+    // Timestamp billing periods and usage resets are counted from, in calendar months
+    pub billing_anchor: Option<u64>,
+    // Overage accrued for the next renewal charge
+    pub overage_due_e8s: Option<u64>,
+}
+
+impl Subscription {
+    /// Subscriptions that predate anchors count from when they were created
+    pub fn billing_anchor(&self) -> u64 {
+        self.billing_anchor.unwrap_or(self.created_at)
+    }
+
+    pub fn active_agent_count(&self) -> u32 {
+        self.active_agents.as_ref().map_or(0, |agents| agents.len() as u32)
+    }
+}
+
+/// Failed renewal bookkeeping for one subscription
+#[derive(Debug, Clone, Default, Serialize, Deserialize, CandidType)]
+pub struct BillingState {
+    pub attempts: u32,
+    pub last_attempt_at: Option<u64>,
+    pub next_attempt_at: Option<u64>,
+    pub grace_ends_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub suspended_at: Option<u64>,
+}
+
+/// How recurring billing charges auto-renewing subscriptions, in nanoseconds
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BillingPolicy {
+    // How long before `expires_at` the first renewal charge is attempted
+    pub renewal_lead: u64,
+    // How long past `expires_at` a failed renewal keeps the subscription before suspension
+    pub grace_period: u64,
+    // First retry delay, doubled per failed attempt up to `max_retry_delay`
+    pub retry_delay: u64,
+    pub max_retry_delay: u64,
+}
+
+impl BillingPolicy {
+    pub const HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.retry_delay == 0 {
+            return Err("Retry delay must be greater than zero".to_string());
+        }
+        if self.max_retry_delay < self.retry_delay {
+            return Err("Max retry delay cannot be shorter than the retry delay".to_string());
+        }
+        Ok(())
+    }
+
+    /// Delay before the next attempt after `attempts` failures
+    pub fn retry_after(&self, attempts: u32) -> u64 {
+        let doublings = attempts.saturating_sub(1).min(32);
+        self.retry_delay.saturating_mul(1u64 << doublings).min(self.max_retry_delay)
+    }
+}
+
+impl Default for BillingPolicy {
+    fn default() -> Self {
+        Self {
+            renewal_lead: 24 * Self::HOUR,
+            grace_period: 7 * 24 * Self::HOUR,
+            retry_delay: Self::HOUR,
+            max_retry_delay: 24 * Self::HOUR,
+        }
+    }
+}
+
+/// Result of the most recent billing run, plus running totals
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct BillingRunStatus {
+    pub last_run_at: u64,
+    pub last_trigger: SweepTrigger,
+    pub last_renewed: u32,
+    pub last_failed: u32,
+    pub last_suspended: u32,
+    // Due renewals and usage resets left for a later run
+    pub backlog: u32,
+    // Subscriptions whose usage rolled into a new period this run
+    pub last_usage_resets: Option<u32>,
+    // Lapsed subscriptions whose outstanding overage was collected this run
+    pub last_overage_collected: Option<u32>,
+    pub total_renewed: u64,
+    pub total_suspended: u64,
+    pub runs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum TierChangeDirection {
+    Upgrade,
+    Downgrade,
+}
+
+/// A priced move between tiers for the remainder of the current period
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TierChange {
+    pub from_tier_id: String,
+    pub to_tier_id: String,
+    pub direction: TierChangeDirection,
+    // Charge for the rest of the period; downgrades wait for period end, so nothing is credited
+    pub prorated_amount_e8s: u64,
+    pub remaining_ns: u64,
+    pub period_ns: u64,
+    pub requested_at: u64,
+    pub effective_at: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -162,6 +709,34 @@ pub struct UsageMetrics {
     pub tokens_used_this_month: u64,
     pub inferences_this_month: u32,
     pub last_reset_date: u64,
+    // Usage past the quota this period and what it was billed
+    pub overage_tokens: Option<u64>,
+    pub overage_agent_creations: Option<u32>,
+    pub overage_charged_e8s: Option<u64>,
+}
+
+impl UsageMetrics {
+    /// Empty usage for a period starting at `period_start`
+    pub fn starting(period_start: u64) -> Self {
+        Self {
+            agents_created_this_month: 0,
+            tokens_used_this_month: 0,
+            inferences_this_month: 0,
+            last_reset_date: period_start,
+            overage_tokens: None,
+            overage_agent_creations: None,
+            overage_charged_e8s: None,
+        }
+    }
+}
+
+/// Usage recorded over one finished billing period
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct UsagePeriod {
+    pub period_start: u64,
+    pub period_end: u64,
+    pub tier_id: Option<String>,
+    pub usage: UsageMetrics,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
@@ -170,6 +745,10 @@ pub enum PaymentStatus {
     Pending,
     Failed,
     Cancelled,
+    // Renewal failed; service continues until the grace period ends
+    GracePeriod,
+    // Grace period ended without payment; quotas are withheld
+    Suspended,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -181,6 +760,65 @@ pub struct TierConfig {
     pub token_limit: u64,
     pub inference_rate: InferenceRate,
     pub features: Vec<String>,
+    // Inferences allowed per month; unlimited when unset
+    pub monthly_inference_limit: Option<u32>,
+    // Usage past `token_limit` and `monthly_agent_creations` is billed instead of refused when set
+    pub overage: Option<OveragePolicy>,
+    // Catalog version of this tier, bumped on every edit
+    pub version: Option<u32>,
+    pub updated_at: Option<u64>,
+    // Retired tiers are not offered to new subscribers
+    pub retired_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq)]
+pub enum OverageSettlement {
+    /// Added to the amount charged at the next renewal
+    Accrue,
+    /// Taken from the econ balance as it is incurred, accruing whatever the balance cannot cover
+    DebitBalance,
+}
+
+/// Soft limits for a tier: usage past the quota is priced per unit, up to a cap
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct OveragePolicy {
+    pub price_per_1k_tokens_e8s: u64,
+    pub price_per_agent_creation_e8s: u64,
+    // Most usage allowed past the quota each period
+    pub max_overage_tokens: u64,
+    pub max_overage_agent_creations: u32,
+    pub settlement: OverageSettlement,
+}
+
+impl TierConfig {
+    pub fn version(&self) -> u32 {
+        self.version.unwrap_or(1)
+    }
+
+    pub fn is_retired(&self) -> bool {
+        self.retired_at.is_some()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Tier name cannot be empty".to_string());
+        }
+        if self.max_agents == 0 {
+            return Err("Tier must allow at least one agent".to_string());
+        }
+        if self.token_limit == 0 {
+            return Err("Tier token limit must be greater than zero".to_string());
+        }
+        if let Some(overage) = &self.overage {
+            if overage.max_overage_tokens > 0 && overage.price_per_1k_tokens_e8s == 0 {
+                return Err("Token overage needs a price".to_string());
+            }
+            if overage.max_overage_agent_creations > 0 && overage.price_per_agent_creation_e8s == 0 {
+                return Err("Agent creation overage needs a price".to_string());
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -195,11 +833,46 @@ pub struct QuotaValidation {
     pub allowed: bool,
     pub reason: Option<String>,
     pub remaining_quota: Option<QuotaRemaining>,
+    // Set when the check held tokens for a later commit
+    pub reservation: Option<QuotaReservation>,
+}
+
+/// Tokens held against a subscriber's quota until the inference reports what it actually used
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct QuotaReservation {
+    pub reservation_id: String,
+    pub principal_id: String,
+    // Caller that made the reservation and may commit or release it
+    pub reserved_by: String,
+    pub tokens: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+/// Limits a subscriber is actually entitled to right now
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct Entitlements {
+    pub principal_id: String,
+    pub subscribed_tier_id: Option<String>,
+    pub effective_tier_id: Option<String>,
+    pub tier: TierConfig,
+    // Why the subscriber is held to the fallback tier instead of the one subscribed to
+    pub fallback_reason: Option<String>,
+    pub payment_status: PaymentStatus,
+    pub expires_at: u64,
+    pub usage: UsageMetrics,
+    pub active_agents: u32,
+    // Tokens held by open reservations; already deducted from `remaining`
+    pub tokens_reserved: u64,
+    pub overage_due_e8s: u64,
+    pub remaining: QuotaRemaining,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
 pub struct QuotaRemaining {
     pub agents_remaining: u32,
     pub tokens_remaining: u64,
+    // `u32::MAX` when the tier sets no inference limit
     pub inferences_remaining: u32,
+    pub concurrent_agents_remaining: u32,
 }
\ No newline at end of file
@@ -0,0 +1,218 @@
+//! Fixed-point money arithmetic.
+//!
+//! Amounts are integer e8s (1 token = 100_000_000 e8s) and rates are basis points
+//! (1 bp = 0.01%, 10_000 bp = 100% or a 1.0x multiplier). Intermediate products are
+//! computed in `u128`, and every operation is checked: results that do not fit in `u64`
+//! return `MoneyError::Overflow` instead of wrapping or saturating.
+//!
+//! Rounding policy:
+//! - Fees and shares (`apply_bps`, `mul_div`) round down, so a split never pays out more than it took
+//!   in; the sub-e8s remainder stays with whoever is left holding the total (payer or treasury).
+//! - `split_by_weights` must pay out the whole amount, so its rounding remainder goes to the first
+//!   (lead) recipient.
+//! - Prices converted into tokens (`from_usd`, `apply_bps_ceil`, `mul_div_ceil`) round up, so the
+//!   protocol never undercharges for a quoted price.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum MoneyError {
+    #[error("amount overflow")]
+    Overflow,
+    #[error("amount underflow")]
+    Underflow,
+    #[error("division by zero")]
+    DivisionByZero,
+}
+
+impl From<MoneyError> for String {
+    fn from(error: MoneyError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A rate in basis points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct BasisPoints(pub u32);
+
+impl BasisPoints {
+    pub const ZERO: BasisPoints = BasisPoints(0);
+    pub const ONE_HUNDRED_PERCENT: BasisPoints = BasisPoints(10_000);
+    pub const DENOMINATOR: u128 = 10_000;
+
+    pub fn from_percent(percent: u32) -> Self {
+        BasisPoints(percent.saturating_mul(100))
+    }
+
+    pub fn value(self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for BasisPoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}bp", self.0)
+    }
+}
+
+/// An amount of tokens in e8s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Money(u64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+    pub const E8S_PER_TOKEN: u64 = 100_000_000;
+
+    pub fn from_e8s(e8s: u64) -> Self {
+        Money(e8s)
+    }
+
+    pub fn e8s(self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.0.checked_add(other.0).map(Money).ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.0.checked_sub(other.0).map(Money).ok_or(MoneyError::Underflow)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Result<Money, MoneyError> {
+        self.0.checked_mul(factor).map(Money).ok_or(MoneyError::Overflow)
+    }
+
+    /// `self * rate`, rounded down
+    pub fn apply_bps(self, rate: BasisPoints) -> Result<Money, MoneyError> {
+        Self::from_u128(self.0 as u128 * rate.0 as u128 / BasisPoints::DENOMINATOR)
+    }
+
+    /// `self * rate`, rounded up
+    pub fn apply_bps_ceil(self, rate: BasisPoints) -> Result<Money, MoneyError> {
+        let product = self.0 as u128 * rate.0 as u128;
+        Self::from_u128(product.div_ceil(BasisPoints::DENOMINATOR))
+    }
+
+    /// `self * numerator / denominator`, rounded down
+    pub fn mul_div(self, numerator: u64, denominator: u64) -> Result<Money, MoneyError> {
+        if denominator == 0 {
+            return Err(MoneyError::DivisionByZero);
+        }
+        Self::from_u128(self.0 as u128 * numerator as u128 / denominator as u128)
+    }
+
+    /// `self * numerator / denominator`, rounded up
+    pub fn mul_div_ceil(self, numerator: u64, denominator: u64) -> Result<Money, MoneyError> {
+        if denominator == 0 {
+            return Err(MoneyError::DivisionByZero);
+        }
+        Self::from_u128((self.0 as u128 * numerator as u128).div_ceil(denominator as u128))
+    }
+
+    /// Divide `self` in proportion to `weights`; the parts always sum to `self`
+    pub fn split_by_weights(self, weights: &[u64]) -> Result<Vec<Money>, MoneyError> {
+        let total_weight = weights.iter().try_fold(0u64, |sum, w| sum.checked_add(*w)).ok_or(MoneyError::Overflow)?;
+        let mut parts = weights
+            .iter()
+            .map(|weight| self.mul_div(*weight, total_weight))
+            .collect::<Result<Vec<Money>, MoneyError>>()?;
+
+        let distributed = parts.iter().try_fold(Money::ZERO, |sum, part| sum.checked_add(*part))?;
+        if let Some(first) = parts.first_mut() {
+            *first = first.checked_add(self.checked_sub(distributed)?)?;
+        }
+        Ok(parts)
+    }
+
+    /// Convert a whole-dollar price into e8s at `usd_e8s_per_token` (USD with 8 decimals per token),
+    /// rounded up
+    pub fn from_usd(amount_usd: u32, usd_e8s_per_token: u64) -> Result<Money, MoneyError> {
+        if usd_e8s_per_token == 0 {
+            return Err(MoneyError::DivisionByZero);
+        }
+        let usd_e8s = amount_usd as u128 * Self::E8S_PER_TOKEN as u128;
+        let numerator = usd_e8s * Self::E8S_PER_TOKEN as u128;
+        Self::from_u128(numerator.div_ceil(usd_e8s_per_token as u128))
+    }
+
+    fn from_u128(value: u128) -> Result<Money, MoneyError> {
+        u64::try_from(value).map(Money).map_err(|_| MoneyError::Overflow)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} e8s", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn e8s(parts: Vec<Money>) -> Vec<u64> {
+        parts.into_iter().map(Money::e8s).collect()
+    }
+
+    #[test]
+    fn apply_bps_rounds_down_and_ceil_rounds_up() {
+        let amount = Money::from_e8s(10_001);
+        assert_eq!(amount.apply_bps(BasisPoints(250)).unwrap(), Money::from_e8s(250));
+        assert_eq!(amount.apply_bps_ceil(BasisPoints(250)).unwrap(), Money::from_e8s(251));
+        assert_eq!(amount.apply_bps(BasisPoints::ONE_HUNDRED_PERCENT).unwrap(), amount);
+        assert_eq!(amount.apply_bps(BasisPoints::ZERO).unwrap(), Money::ZERO);
+        // Exact products are not bumped up
+        assert_eq!(Money::from_e8s(10_000).apply_bps_ceil(BasisPoints(250)).unwrap(), Money::from_e8s(250));
+    }
+
+    #[test]
+    fn mul_div_rounding() {
+        let amount = Money::from_e8s(100);
+        assert_eq!(amount.mul_div(1, 3).unwrap(), Money::from_e8s(33));
+        assert_eq!(amount.mul_div_ceil(1, 3).unwrap(), Money::from_e8s(34));
+        assert_eq!(amount.mul_div(3, 4).unwrap(), Money::from_e8s(75));
+        assert_eq!(amount.mul_div_ceil(3, 4).unwrap(), Money::from_e8s(75));
+        // The u128 intermediate keeps large products exact
+        assert_eq!(Money::from_e8s(u64::MAX).mul_div(u64::MAX, u64::MAX).unwrap(), Money::from_e8s(u64::MAX));
+    }
+
+    #[test]
+    fn split_by_weights_gives_the_remainder_to_the_first_share() {
+        let parts = Money::from_e8s(100).split_by_weights(&[1, 1, 1]).unwrap();
+        assert_eq!(e8s(parts), vec![34, 33, 33]);
+
+        let parts = Money::from_e8s(1_000).split_by_weights(&[0, 7, 3]).unwrap();
+        assert_eq!(e8s(parts), vec![0, 700, 300]);
+
+        let parts = Money::from_e8s(11).split_by_weights(&[3, 3, 3, 1]).unwrap();
+        assert_eq!(e8s(parts.clone()), vec![4, 3, 3, 1]);
+        assert_eq!(parts.into_iter().map(Money::e8s).sum::<u64>(), 11);
+    }
+
+    #[test]
+    fn from_usd_rounds_up_to_the_next_e8() {
+        // $10 at $5.00 per token is exactly 2 tokens
+        assert_eq!(Money::from_usd(10, 500_000_000).unwrap(), Money::from_e8s(200_000_000));
+        // $1 at $3.00 per token is 0.33333333.. tokens
+        assert_eq!(Money::from_usd(1, 300_000_000).unwrap(), Money::from_e8s(33_333_334));
+        assert_eq!(Money::from_usd(0, 300_000_000).unwrap(), Money::ZERO);
+    }
+
+    #[test]
+    fn overflow_and_division_by_zero_are_errors() {
+        let max = Money::from_e8s(u64::MAX);
+        assert_eq!(max.checked_add(Money::from_e8s(1)), Err(MoneyError::Overflow));
+        assert_eq!(Money::ZERO.checked_sub(Money::from_e8s(1)), Err(MoneyError::Underflow));
+        assert_eq!(max.checked_mul(2), Err(MoneyError::Overflow));
+        assert_eq!(max.apply_bps(BasisPoints(20_000)), Err(MoneyError::Overflow));
+        assert_eq!(max.mul_div(2, 1), Err(MoneyError::Overflow));
+        assert_eq!(max.mul_div_ceil(3, 2), Err(MoneyError::Overflow));
+        assert_eq!(Money::from_usd(u32::MAX, 1), Err(MoneyError::Overflow));
+        assert_eq!(Money::from_usd(10, 0), Err(MoneyError::DivisionByZero));
+        assert_eq!(max.mul_div(1, 0), Err(MoneyError::DivisionByZero));
+        assert_eq!(max.mul_div_ceil(1, 0), Err(MoneyError::DivisionByZero));
+        assert_eq!(Money::from_e8s(100).split_by_weights(&[0, 0]), Err(MoneyError::DivisionByZero));
+        assert_eq!(Money::from_e8s(100).split_by_weights(&[u64::MAX, 1]), Err(MoneyError::Overflow));
+    }
+}
@@ -1,7 +1,7 @@
-use ic_cdk::api::caller;
+use crate::infra::runtime::caller;
 use candid::Principal;
 use crate::domain::*;
-use crate::services::{is_admin};
+use crate::services::{is_admin, DisputeService, RegistryService};
 
 pub struct Guards;
 
@@ -21,6 +21,24 @@ impl Guards {
         if is_admin(&text) { Ok(()) } else { Err("Admin required".to_string()) }
     }
     
+    pub fn require_arbiter() -> Result<(), String> {
+        Self::require_caller_authenticated()?;
+        let text = caller().to_text();
+        if is_admin(&text) || DisputeService::is_arbiter(&text) { Ok(()) } else { Err("Admin or arbiter required".to_string()) }
+    }
+    
+    pub fn require_coordinator() -> Result<(), String> {
+        Self::require_caller_authenticated()?;
+        let text = caller().to_text();
+        if is_admin(&text) || RegistryService::is_coordinator(&text) { Ok(()) } else { Err("Admin or coordinator required".to_string()) }
+    }
+    
+    /// Calls that bill `principal_id` may come from that principal or a coordinator acting for it
+    pub fn require_self_or_coordinator(principal_id: &str) -> Result<(), String> {
+        Self::require_caller_authenticated()?;
+        if caller().to_text() == principal_id { Ok(()) } else { Self::require_coordinator() }
+    }
+    
     pub fn validate_amount(amount: u64) -> Result<(), String> {
         if amount == 0 {
             return Err("Amount must be greater than zero".to_string());
@@ -67,14 +85,20 @@ impl Guards {
         }
         
         // Validate fees breakdown
-        let expected_total = receipt.fees_breakdown.base_amount 
-            + receipt.fees_breakdown.protocol_fee 
-            + receipt.fees_breakdown.agent_fee;
+        let expected_total = Money::from_e8s(receipt.fees_breakdown.base_amount)
+            .checked_add(Money::from_e8s(receipt.fees_breakdown.protocol_fee))
+            .and_then(|total| total.checked_add(Money::from_e8s(receipt.fees_breakdown.agent_fee)))
+            .map_err(|e| format!("Invalid fees breakdown: {}", e))?;
             
-        if receipt.fees_breakdown.total_amount != expected_total {
+        if receipt.fees_breakdown.total_amount != expected_total.e8s() {
             return Err("Fees breakdown does not match total amount".to_string());
         }
         
+        // The settled amount is split exactly into agent payout and protocol fee
+        if receipt.actual_cost != receipt.fees_breakdown.total_amount {
+            return Err("Actual cost must equal fees breakdown total".to_string());
+        }
+        
         Ok(())
     }
 }
\ No newline at end of file
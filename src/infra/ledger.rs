@@ -0,0 +1,234 @@
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use crate::services::with_state;
+#[cfg(not(target_arch = "wasm32"))]
+use super::mock_ledger::MockLedger;
+
+/// Default ICP ledger canister ID, used until an admin configures another ICRC-1 ledger
+pub const ICP_LEDGER_CANISTER_ID: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
+
+// ICRC-1 ledger types
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq, Eq, Hash)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+// ICRC-2 ledger types
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// Thin client over the configured ICRC-1 ledger
+pub struct LedgerClient;
+
+impl LedgerClient {
+    pub fn ledger_id() -> Result<Principal, String> {
+        let configured = with_state(|state| state.ledger_canister_id.clone());
+        let text = configured.unwrap_or_else(|| ICP_LEDGER_CANISTER_ID.to_string());
+        Principal::from_text(&text).map_err(|e| format!("Invalid ledger principal: {}", e))
+    }
+
+    /// Deterministic per-principal subaccount: length-prefixed principal bytes, zero padded
+    pub fn principal_subaccount(principal: &Principal) -> [u8; 32] {
+        let bytes = principal.as_slice();
+        let mut subaccount = [0u8; 32];
+        subaccount[0] = bytes.len() as u8;
+        subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+        subaccount
+    }
+
+    /// Account of this canister, optionally under a subaccount
+    pub fn canister_account(subaccount: Option<[u8; 32]>) -> Account {
+        Account {
+            owner: super::runtime::canister_id(),
+            subaccount: subaccount.map(|s| s.to_vec()),
+        }
+    }
+
+    pub fn deposit_account(principal: &Principal) -> Account {
+        Self::canister_account(Some(Self::principal_subaccount(principal)))
+    }
+
+    pub async fn balance_of(account: Account) -> Result<u64, String> {
+        #[cfg(target_arch = "wasm32")]
+        let balance = {
+            let ledger = Self::ledger_id()?;
+            let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+                .await
+                .map_err(|(code, msg)| format!("Ledger call failed: {} - {}", code as u8, msg))?;
+            balance
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let balance = MockLedger::icrc1_balance_of(account);
+
+        nat_to_u64(&balance)
+    }
+
+    pub async fn fee() -> Result<u64, String> {
+        #[cfg(target_arch = "wasm32")]
+        let fee = {
+            let ledger = Self::ledger_id()?;
+            let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+                .await
+                .map_err(|(code, msg)| format!("Ledger call failed: {} - {}", code as u8, msg))?;
+            fee
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let fee = MockLedger::icrc1_fee();
+
+        nat_to_u64(&fee)
+    }
+
+    /// Transfer from one of this canister's accounts; returns the ledger block index
+    pub async fn transfer(arg: TransferArg) -> Result<u64, String> {
+        match Self::try_transfer(arg).await? {
+            Ok(block_index) => Ok(block_index),
+            Err(e) => Err(describe_transfer_error(&e)),
+        }
+    }
+
+    /// Transfer keeping the ledger's answer apart from a rejected call. `Err` means the call was
+    /// rejected and the transfer may or may not have executed; `Ok(Err(_))` is a definite refusal.
+    pub async fn try_transfer(arg: TransferArg) -> Result<Result<u64, TransferError>, String> {
+        #[cfg(target_arch = "wasm32")]
+        let result = {
+            let ledger = Self::ledger_id()?;
+            let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (arg,))
+                .await
+                .map_err(|(code, msg)| format!("Ledger call failed: {} - {}", code as u8, msg))?;
+            result
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let result = MockLedger::call(|| MockLedger::icrc1_transfer(super::runtime::canister_id(), arg))?;
+
+        match result {
+            Ok(block_index) => nat_to_u64(&block_index).map(Ok),
+            Err(e) => Ok(Err(e)),
+        }
+    }
+
+    /// Pull from `from` into one of this canister's accounts using an ICRC-2 allowance, keeping
+    /// the ledger's answer apart from a rejected call as `try_transfer` does. Callers keep the
+    /// arguments of a pull whose outcome is unknown and retry them unchanged.
+    pub async fn try_transfer_from(args: TransferFromArgs) -> Result<Result<u64, TransferFromError>, String> {
+        #[cfg(target_arch = "wasm32")]
+        let result = {
+            let ledger = Self::ledger_id()?;
+            let (result,): (Result<Nat, TransferFromError>,) = ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+                .await
+                .map_err(|(code, msg)| format!("Ledger call failed: {} - {}", code as u8, msg))?;
+            result
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        let result = MockLedger::call(|| MockLedger::icrc2_transfer_from(super::runtime::canister_id(), args))?;
+
+        match result {
+            Ok(block_index) => nat_to_u64(&block_index).map(Ok),
+            Err(e) => Ok(Err(e)),
+        }
+    }
+}
+
+pub fn nat_to_u64(value: &Nat) -> Result<u64, String> {
+    value.0.to_string()
+        .parse::<u64>()
+        .map_err(|_| format!("Ledger amount out of range: {}", value.0))
+}
+
+pub fn describe_transfer_error(error: &TransferError) -> String {
+    match error {
+        TransferError::BadFee { expected_fee } => format!("Bad fee: ledger expects {}", expected_fee.0),
+        TransferError::BadBurn { min_burn_amount } => format!("Bad burn: minimum is {}", min_burn_amount.0),
+        TransferError::InsufficientFunds { balance } => format!("Insufficient ledger funds: balance {}", balance.0),
+        TransferError::TooOld => "Transfer too old".to_string(),
+        TransferError::CreatedInFuture { ledger_time } => format!("Transfer created in the future (ledger time {})", ledger_time),
+        TransferError::Duplicate { duplicate_of } => format!("Duplicate of block {}", duplicate_of.0),
+        TransferError::TemporarilyUnavailable => "Ledger temporarily unavailable".to_string(),
+        TransferError::GenericError { error_code, message } => format!("Ledger error {}: {}", error_code.0, message),
+    }
+}
+
+pub fn describe_transfer_from_error(error: &TransferFromError, amount: u64) -> String {
+    match error {
+        TransferFromError::InsufficientAllowance { allowance } => format!(
+            "Insufficient allowance: approved {} but {} plus the ledger fee is required; call icrc2_approve for this canister first",
+            allowance.0, amount
+        ),
+        TransferFromError::BadFee { expected_fee } => format!("Bad fee: ledger expects {}", expected_fee.0),
+        TransferFromError::TooOld => "Payment request too old; create a new one and retry".to_string(),
+        TransferFromError::Duplicate { duplicate_of } => format!("Duplicate of block {}", duplicate_of.0),
+        TransferFromError::InsufficientFunds { balance } => format!("Insufficient ledger funds: balance {}", balance.0),
+        TransferFromError::BadBurn { min_burn_amount } => format!("Bad burn: minimum is {}", min_burn_amount.0),
+        TransferFromError::CreatedInFuture { ledger_time } => format!("Transfer created in the future (ledger time {})", ledger_time),
+        TransferFromError::TemporarilyUnavailable => "Ledger temporarily unavailable".to_string(),
+        TransferFromError::GenericError { error_code, message } => format!("Ledger error {}: {}", error_code.0, message),
+    }
+}
@@ -0,0 +1,32 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+thread_local! {
+    static IN_FLIGHT: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Held across an `await` so a second call for the same key fails fast instead of interleaving.
+/// Released on drop, including when the call future is cleaned up after a trap.
+pub struct InFlightLock {
+    key: String,
+}
+
+impl InFlightLock {
+    pub fn acquire(key: String) -> Result<Self, String> {
+        IN_FLIGHT.with(|locks| {
+            if locks.borrow_mut().insert(key.clone()) {
+                Ok(Self { key })
+            } else {
+                Err(format!("Operation already in progress: {}", key))
+            }
+        })
+    }
+}
+
+impl Drop for InFlightLock {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|locks| {
+            locks.borrow_mut().remove(&self.key);
+        });
+    }
+}
@@ -0,0 +1,391 @@
+//! In-process ICRC-1/ICRC-2 ledger used by native (non-wasm) builds so the deposit and withdrawal
+//! flows can be exercised without a replica. Mirrors the ledger's fee, balance, dedup and
+//! transaction-window semantics closely enough for unit tests, reading the clock from the runtime
+//! seam. Faults can be injected to simulate rejected transfer calls.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use candid::{Nat, Principal};
+use crate::infra::runtime::time;
+use crate::infra::ledger::{
+    nat_to_u64, Account, ApproveArgs, ApproveError, TransferArg, TransferError, TransferFromArgs,
+    TransferFromError,
+};
+
+const TRANSACTION_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000;
+const PERMITTED_DRIFT: u64 = 60 * 1_000_000_000;
+
+// (from, to, amount, memo, created_at_time)
+type DedupKey = (Account, Account, u64, Option<Vec<u8>>, u64);
+
+struct MockLedgerState {
+    balances: HashMap<Account, u64>,
+    // (owner, spender) -> allowance
+    allowances: HashMap<(Account, Account), u64>,
+    fee: u64,
+    next_block: u64,
+    // Deduplicated transfers -> block index
+    seen: HashMap<DedupKey, u64>,
+    fault: Option<MockFault>,
+}
+
+/// How the next transfer call fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFault {
+    // Rejected before the ledger ran it
+    Reject,
+    // Executed, but the reply never reached the caller
+    LoseReply,
+}
+
+impl Default for MockLedgerState {
+    fn default() -> Self {
+        Self {
+            balances: HashMap::new(),
+            allowances: HashMap::new(),
+            fee: 10_000,
+            next_block: 0,
+            seen: HashMap::new(),
+            fault: None,
+        }
+    }
+}
+
+thread_local! {
+    static LEDGER: RefCell<MockLedgerState> = RefCell::new(MockLedgerState::default());
+}
+
+pub struct MockLedger;
+
+impl MockLedger {
+    pub fn reset() {
+        LEDGER.with(|l| *l.borrow_mut() = MockLedgerState::default());
+    }
+
+    pub fn set_fee(fee: u64) {
+        LEDGER.with(|l| l.borrow_mut().fee = fee);
+    }
+
+    /// Make the next transfer or transfer_from call fail
+    pub fn inject_fault(fault: MockFault) {
+        LEDGER.with(|l| l.borrow_mut().fault = Some(fault));
+    }
+
+    /// Run a transfer method as an inter-canister call would, applying any injected fault
+    pub fn call<T>(method: impl FnOnce() -> T) -> Result<T, String> {
+        match LEDGER.with(|l| l.borrow_mut().fault.take()) {
+            None => Ok(method()),
+            Some(MockFault::Reject) => Err("Ledger call failed: 2 - injected reject".to_string()),
+            Some(MockFault::LoseReply) => {
+                method();
+                Err("Ledger call failed: 5 - injected lost reply".to_string())
+            }
+        }
+    }
+
+    /// Credit an account out of thin air, as a minting transfer would
+    pub fn mint(account: Account, amount: u64) -> u64 {
+        LEDGER.with(|l| {
+            let mut ledger = l.borrow_mut();
+            *ledger.balances.entry(normalize(account)).or_insert(0) += amount;
+            let block = ledger.next_block;
+            ledger.next_block += 1;
+            block
+        })
+    }
+
+    pub fn icrc1_fee() -> Nat {
+        LEDGER.with(|l| Nat::from(l.borrow().fee))
+    }
+
+    pub fn icrc1_balance_of(account: Account) -> Nat {
+        LEDGER.with(|l| Nat::from(l.borrow().balances.get(&normalize(account)).copied().unwrap_or(0)))
+    }
+
+    pub fn icrc1_transfer(caller: Principal, arg: TransferArg) -> Result<Nat, TransferError> {
+        let from = normalize(Account { owner: caller, subaccount: arg.from_subaccount.clone() });
+        Self::debit_and_credit(from, arg)
+    }
+
+    pub fn icrc2_approve(caller: Principal, args: ApproveArgs) -> Result<Nat, ApproveError> {
+        let owner = normalize(Account { owner: caller, subaccount: args.from_subaccount.clone() });
+        let spender = normalize(args.spender.clone());
+        let amount = nat_to_u64(&args.amount).map_err(|message| ApproveError::GenericError {
+            error_code: Nat::from(0u64),
+            message,
+        })?;
+
+        LEDGER.with(|l| {
+            let mut ledger = l.borrow_mut();
+            let fee = ledger.fee;
+            let current = ledger.allowances.get(&(owner.clone(), spender.clone())).copied().unwrap_or(0);
+            if let Some(expected) = args.expected_allowance.as_ref() {
+                if nat_to_u64(expected).ok() != Some(current) {
+                    return Err(ApproveError::AllowanceChanged { current_allowance: Nat::from(current) });
+                }
+            }
+
+            let balance = ledger.balances.get(&owner).copied().unwrap_or(0);
+            if balance < fee {
+                return Err(ApproveError::InsufficientFunds { balance: Nat::from(balance) });
+            }
+
+            ledger.balances.insert(owner.clone(), balance - fee);
+            ledger.allowances.insert((owner, spender), amount);
+            let block = ledger.next_block;
+            ledger.next_block += 1;
+            Ok(Nat::from(block))
+        })
+    }
+
+    pub fn icrc2_allowance(owner: Account, spender: Account) -> Nat {
+        LEDGER.with(|l| {
+            let ledger = l.borrow();
+            Nat::from(ledger.allowances.get(&(normalize(owner), normalize(spender))).copied().unwrap_or(0))
+        })
+    }
+
+    pub fn icrc2_transfer_from(caller: Principal, args: TransferFromArgs) -> Result<Nat, TransferFromError> {
+        let spender = normalize(Account { owner: caller, subaccount: args.spender_subaccount.clone() });
+        let from = normalize(args.from.clone());
+        let amount = nat_to_u64(&args.amount).map_err(|message| TransferFromError::GenericError {
+            error_code: Nat::from(0u64),
+            message,
+        })?;
+
+        // A replayed pull reports the original block even though the allowance is already spent
+        if let Some(created_at) = args.created_at_time {
+            let key = (from.clone(), normalize(args.to.clone()), amount, args.memo.clone(), created_at);
+            if let Some(block) = LEDGER.with(|l| l.borrow().seen.get(&key).copied()) {
+                return Err(TransferFromError::Duplicate { duplicate_of: Nat::from(block) });
+            }
+        }
+
+        let allowance = LEDGER.with(|l| {
+            l.borrow().allowances.get(&(from.clone(), spender.clone())).copied().unwrap_or(0)
+        });
+        let fee = LEDGER.with(|l| l.borrow().fee);
+        if allowance < amount + fee {
+            return Err(TransferFromError::InsufficientAllowance { allowance: Nat::from(allowance) });
+        }
+
+        let arg = TransferArg {
+            from_subaccount: from.subaccount.clone(),
+            to: args.to,
+            amount: args.amount,
+            fee: args.fee,
+            memo: args.memo,
+            created_at_time: args.created_at_time,
+        };
+        let block = Self::debit_and_credit(from.clone(), arg).map_err(|e| match e {
+            TransferError::BadFee { expected_fee } => TransferFromError::BadFee { expected_fee },
+            TransferError::BadBurn { min_burn_amount } => TransferFromError::BadBurn { min_burn_amount },
+            TransferError::InsufficientFunds { balance } => TransferFromError::InsufficientFunds { balance },
+            TransferError::TooOld => TransferFromError::TooOld,
+            TransferError::CreatedInFuture { ledger_time } => TransferFromError::CreatedInFuture { ledger_time },
+            TransferError::Duplicate { duplicate_of } => TransferFromError::Duplicate { duplicate_of },
+            TransferError::TemporarilyUnavailable => TransferFromError::TemporarilyUnavailable,
+            TransferError::GenericError { error_code, message } => TransferFromError::GenericError { error_code, message },
+        })?;
+
+        LEDGER.with(|l| {
+            l.borrow_mut().allowances.insert((from, spender), allowance - amount - fee);
+        });
+        Ok(block)
+    }
+
+    fn debit_and_credit(from: Account, arg: TransferArg) -> Result<Nat, TransferError> {
+        let amount = nat_to_u64(&arg.amount).map_err(|message| TransferError::GenericError {
+            error_code: Nat::from(0u64),
+            message,
+        })?;
+        let to = normalize(arg.to.clone());
+
+        LEDGER.with(|l| {
+            let mut ledger = l.borrow_mut();
+
+            if let Some(fee) = arg.fee.as_ref() {
+                if nat_to_u64(fee).ok() != Some(ledger.fee) {
+                    return Err(TransferError::BadFee { expected_fee: Nat::from(ledger.fee) });
+                }
+            }
+
+            let dedup_key = arg.created_at_time.map(|created_at| {
+                (from.clone(), to.clone(), amount, arg.memo.clone(), created_at)
+            });
+            if let Some(created_at) = arg.created_at_time {
+                let now = time();
+                if created_at + TRANSACTION_WINDOW + PERMITTED_DRIFT < now {
+                    return Err(TransferError::TooOld);
+                }
+                if created_at > now + PERMITTED_DRIFT {
+                    return Err(TransferError::CreatedInFuture { ledger_time: now });
+                }
+            }
+            if let Some(block) = dedup_key.as_ref().and_then(|key| ledger.seen.get(key)) {
+                return Err(TransferError::Duplicate { duplicate_of: Nat::from(*block) });
+            }
+
+            let fee = ledger.fee;
+            let balance = ledger.balances.get(&from).copied().unwrap_or(0);
+            if balance < amount + fee {
+                return Err(TransferError::InsufficientFunds { balance: Nat::from(balance) });
+            }
+
+            ledger.balances.insert(from, balance - amount - fee);
+            *ledger.balances.entry(to).or_insert(0) += amount;
+
+            let block = ledger.next_block;
+            ledger.next_block += 1;
+            if let Some(key) = dedup_key {
+                ledger.seen.insert(key, block);
+            }
+            Ok(Nat::from(block))
+        })
+    }
+}
+
+// The all-zero subaccount and no subaccount name the same account
+fn normalize(account: Account) -> Account {
+    match account.subaccount {
+        Some(ref s) if s.iter().all(|b| *b == 0) => Account { owner: account.owner, subaccount: None },
+        _ => account,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::ledger::LedgerClient;
+    use crate::infra::runtime;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id; 10])
+    }
+
+    fn account(id: u8) -> Account {
+        Account { owner: principal(id), subaccount: None }
+    }
+
+    fn transfer_arg(to: Account, amount: u64, created_at_time: Option<u64>) -> TransferArg {
+        TransferArg {
+            from_subaccount: None,
+            to,
+            amount: Nat::from(amount),
+            fee: None,
+            memo: Some(b"memo".to_vec()),
+            created_at_time,
+        }
+    }
+
+    #[test]
+    fn transfer_moves_amount_and_burns_fee() {
+        MockLedger::reset();
+        MockLedger::mint(account(1), 100_000);
+
+        MockLedger::icrc1_transfer(principal(1), transfer_arg(account(2), 50_000, None)).unwrap();
+
+        assert_eq!(MockLedger::icrc1_balance_of(account(1)), Nat::from(40_000u64));
+        assert_eq!(MockLedger::icrc1_balance_of(account(2)), Nat::from(50_000u64));
+    }
+
+    #[test]
+    fn transfer_rejects_insufficient_funds_and_bad_fee() {
+        MockLedger::reset();
+        MockLedger::mint(account(1), 50_000);
+
+        let result = MockLedger::icrc1_transfer(principal(1), transfer_arg(account(2), 45_000, None));
+        assert!(matches!(result, Err(TransferError::InsufficientFunds { .. })));
+
+        let mut arg = transfer_arg(account(2), 1_000, None);
+        arg.fee = Some(Nat::from(1u64));
+        let result = MockLedger::icrc1_transfer(principal(1), arg);
+        assert!(matches!(result, Err(TransferError::BadFee { .. })));
+        assert_eq!(MockLedger::icrc1_balance_of(account(1)), Nat::from(50_000u64));
+    }
+
+    #[test]
+    fn transfer_with_created_at_time_is_deduplicated() {
+        MockLedger::reset();
+        runtime::set_time(1_000);
+        MockLedger::mint(account(1), 100_000);
+
+        let first = MockLedger::icrc1_transfer(principal(1), transfer_arg(account(2), 10_000, Some(1_000))).unwrap();
+        let replay = MockLedger::icrc1_transfer(principal(1), transfer_arg(account(2), 10_000, Some(1_000)));
+
+        assert!(matches!(replay, Err(TransferError::Duplicate { duplicate_of }) if duplicate_of == first));
+        assert_eq!(MockLedger::icrc1_balance_of(account(2)), Nat::from(10_000u64));
+    }
+
+    #[test]
+    fn transfer_outside_window_is_rejected() {
+        MockLedger::reset();
+        runtime::set_time(TRANSACTION_WINDOW + PERMITTED_DRIFT + 10);
+        MockLedger::mint(account(1), 100_000);
+
+        let too_old = MockLedger::icrc1_transfer(principal(1), transfer_arg(account(2), 1_000, Some(1)));
+        assert!(matches!(too_old, Err(TransferError::TooOld)));
+
+        let future = TRANSACTION_WINDOW + 2 * PERMITTED_DRIFT + 20;
+        let in_future = MockLedger::icrc1_transfer(principal(1), transfer_arg(account(2), 1_000, Some(future)));
+        assert!(matches!(in_future, Err(TransferError::CreatedInFuture { .. })));
+    }
+
+    #[test]
+    fn transfer_from_spends_allowance() {
+        MockLedger::reset();
+        MockLedger::mint(account(1), 100_000);
+        let approve = ApproveArgs {
+            from_subaccount: None,
+            spender: account(9),
+            amount: Nat::from(30_000u64),
+            expected_allowance: None,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        MockLedger::icrc2_approve(principal(1), approve).unwrap();
+
+        let pull = |amount: u64| TransferFromArgs {
+            spender_subaccount: None,
+            from: account(1),
+            to: account(2),
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        MockLedger::icrc2_transfer_from(principal(9), pull(15_000)).unwrap();
+
+        assert_eq!(MockLedger::icrc2_allowance(account(1), account(9)), Nat::from(5_000u64));
+        assert_eq!(MockLedger::icrc1_balance_of(account(1)), Nat::from(65_000u64));
+        assert_eq!(MockLedger::icrc1_balance_of(account(2)), Nat::from(15_000u64));
+
+        let over = MockLedger::icrc2_transfer_from(principal(9), pull(15_000));
+        assert!(matches!(over, Err(TransferFromError::InsufficientAllowance { .. })));
+    }
+
+    #[test]
+    fn deposit_subaccounts_are_distinct_per_principal() {
+        MockLedger::reset();
+        let owner = principal(7);
+        let deposit = |id: u8| Account {
+            owner,
+            subaccount: Some(LedgerClient::principal_subaccount(&principal(id)).to_vec()),
+        };
+        MockLedger::mint(deposit(1), 25_000);
+
+        assert_eq!(LedgerClient::principal_subaccount(&principal(1)), LedgerClient::principal_subaccount(&principal(1)));
+        assert_eq!(MockLedger::icrc1_balance_of(deposit(1)), Nat::from(25_000u64));
+        assert_eq!(MockLedger::icrc1_balance_of(deposit(2)), Nat::from(0u64));
+        assert_eq!(MockLedger::icrc1_balance_of(Account { owner, subaccount: None }), Nat::from(0u64));
+    }
+
+    #[test]
+    fn zero_subaccount_is_the_default_account() {
+        MockLedger::reset();
+        MockLedger::mint(Account { owner: principal(1), subaccount: Some(vec![0; 32]) }, 5_000);
+
+        assert_eq!(MockLedger::icrc1_balance_of(account(1)), Nat::from(5_000u64));
+    }
+}
@@ -1,5 +1,16 @@
 pub mod guards;
 pub mod metrics;
+pub mod ledger;
+pub mod locks;
+pub mod runtime;
+pub mod signatures;
+pub mod timers;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mock_ledger;
 
 pub use guards::Guards;
-pub use metrics::Metrics;
\ No newline at end of file
+pub use metrics::Metrics;
+pub use ledger::LedgerClient;
+pub use locks::InFlightLock;
+pub use signatures::ReceiptSignature;
+pub use timers::PeriodicTimer;
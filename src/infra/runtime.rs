@@ -0,0 +1,69 @@
+//! System API seam. Canister builds call straight into `ic_cdk`; native builds read a per-thread
+//! clock and identity instead, so services can run against the mock ledger in unit tests.
+#[cfg(target_arch = "wasm32")]
+use candid::Principal;
+
+#[cfg(target_arch = "wasm32")]
+pub fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn caller() -> Principal {
+    ic_cdk::api::caller()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn canister_id() -> Principal {
+    ic_cdk::api::id()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use candid::Principal;
+    use std::cell::Cell;
+
+    thread_local! {
+        static NOW: Cell<u64> = const { Cell::new(0) };
+        static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+        static CANISTER_ID: Cell<Principal> = const { Cell::new(Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1])) };
+    }
+
+    pub fn time() -> u64 {
+        NOW.with(Cell::get)
+    }
+
+    pub fn caller() -> Principal {
+        CALLER.with(Cell::get)
+    }
+
+    pub fn canister_id() -> Principal {
+        CANISTER_ID.with(Cell::get)
+    }
+
+    pub fn set_time(now: u64) {
+        NOW.with(|cell| cell.set(now));
+    }
+
+    pub fn advance_time(by: u64) {
+        NOW.with(|cell| cell.set(cell.get().saturating_add(by)));
+    }
+
+    pub fn set_caller(principal: Principal) {
+        CALLER.with(|cell| cell.set(principal));
+    }
+
+    /// Drive a service future to completion. Native ledger calls never suspend, so one poll suffices.
+    #[cfg(test)]
+    pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Waker};
+        let mut future = std::pin::pin!(future);
+        match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future suspended without a native ledger call"),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Ed25519 receipt signatures.
+//!
+//! Agents sign `DOMAIN_SEPARATOR || cbor(payload)`, where the payload holds the receipt fields the
+//! agent attests to. Settlement-side fields (status, lines, fee deviation, the signature itself)
+//! are excluded. Payload fields are declared in canonical CBOR key order (shorter keys first, then
+//! bytewise), so serde_cbor's declaration-order maps are already canonical.
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use serde::Serialize;
+use crate::domain::*;
+
+const DOMAIN_SEPARATOR: &[u8] = b"\x0Cohms-receipt";
+
+#[derive(Serialize)]
+struct FeesPayload {
+    agent_fee: u64,
+    base_amount: u64,
+    protocol_fee: u64,
+    total_amount: u64,
+}
+
+#[derive(Serialize)]
+struct SharePayload<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<u32>,
+    agent_id: &'a str,
+}
+
+#[derive(Serialize)]
+struct ReceiptPayload<'a> {
+    job_id: &'a str,
+    // Omitted for single-agent receipts so their payload is unchanged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shares: Option<Vec<SharePayload<'a>>>,
+    agent_id: &'a str,
+    escrow_id: &'a str,
+    created_at: u64,
+    receipt_id: &'a str,
+    actual_cost: u64,
+    close_escrow: Option<bool>,
+    fees_breakdown: FeesPayload,
+}
+
+pub struct ReceiptSignature;
+
+impl ReceiptSignature {
+    /// Bytes an agent signs for `receipt`
+    pub fn signing_payload(receipt: &Receipt) -> Result<Vec<u8>, String> {
+        let payload = ReceiptPayload {
+            job_id: &receipt.job_id,
+            shares: receipt.shares.as_ref().map(|shares| {
+                shares
+                    .iter()
+                    .map(|share| SharePayload {
+                        amount: match share.share { ShareSpec::Amount(amount) => Some(amount), ShareSpec::Weight(_) => None },
+                        weight: match share.share { ShareSpec::Weight(weight) => Some(weight), ShareSpec::Amount(_) => None },
+                        agent_id: &share.agent_id,
+                    })
+                    .collect()
+            }),
+            agent_id: &receipt.agent_id,
+            escrow_id: &receipt.escrow_id,
+            created_at: receipt.created_at,
+            receipt_id: &receipt.receipt_id,
+            actual_cost: receipt.actual_cost,
+            close_escrow: receipt.close_escrow,
+            fees_breakdown: FeesPayload {
+                agent_fee: receipt.fees_breakdown.agent_fee,
+                base_amount: receipt.fees_breakdown.base_amount,
+                protocol_fee: receipt.fees_breakdown.protocol_fee,
+                total_amount: receipt.fees_breakdown.total_amount,
+            },
+        };
+
+        let encoded = serde_cbor::to_vec(&payload)
+            .map_err(|e| format!("Failed to encode receipt: {}", e))?;
+        let mut message = DOMAIN_SEPARATOR.to_vec();
+        message.extend_from_slice(&encoded);
+        Ok(message)
+    }
+
+    pub fn parse_public_key(bytes: &[u8]) -> Result<VerifyingKey, String> {
+        let key: [u8; PUBLIC_KEY_LENGTH] = bytes
+            .try_into()
+            .map_err(|_| format!("Public key must be {} bytes", PUBLIC_KEY_LENGTH))?;
+        VerifyingKey::from_bytes(&key).map_err(|_| "Invalid Ed25519 public key".to_string())
+    }
+
+    /// Check the receipt carries a valid signature by `public_key`
+    pub fn verify(receipt: &Receipt, public_key: &[u8]) -> Result<(), String> {
+        let signature = receipt.signature
+            .as_ref()
+            .ok_or_else(|| "Receipt is not signed".to_string())?;
+        let signature: [u8; SIGNATURE_LENGTH] = signature
+            .as_slice()
+            .try_into()
+            .map_err(|_| format!("Signature must be {} bytes", SIGNATURE_LENGTH))?;
+
+        let key = Self::parse_public_key(public_key)?;
+        let message = Self::signing_payload(receipt)?;
+        key.verify_strict(&message, &Signature::from_bytes(&signature))
+            .map_err(|_| "Receipt signature does not match the agent's key".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn receipt(shares: Option<Vec<PayoutShare>>) -> Receipt {
+        Receipt {
+            receipt_id: "receipt_1".to_string(),
+            job_id: "job_1".to_string(),
+            escrow_id: "escrow_1".to_string(),
+            agent_id: "agent_1".to_string(),
+            actual_cost: 1_000,
+            fees_breakdown: FeesBreakdown { base_amount: 1_000, protocol_fee: 30, agent_fee: 970, total_amount: 1_000 },
+            settlement_status: SettlementStatus::Pending,
+            created_at: 42,
+            settled_at: None,
+            lines: None,
+            close_escrow: Some(true),
+            fee_deviation: None,
+            signature: None,
+            payout_hold: None,
+            dispute: None,
+            status_history: None,
+            shares,
+        }
+    }
+
+    fn signed(mut receipt: Receipt, key: &SigningKey) -> Receipt {
+        let message = ReceiptSignature::signing_payload(&receipt).unwrap();
+        receipt.signature = Some(key.sign(&message).to_bytes().to_vec());
+        receipt
+    }
+
+    fn position(haystack: &[u8], needle: &str) -> usize {
+        haystack.windows(needle.len()).position(|window| window == needle.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn signed_receipt_verifies() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let shares = vec![PayoutShare { agent_id: "agent_2".to_string(), share: ShareSpec::Weight(3) }];
+
+        for receipt in [signed(receipt(None), &key), signed(receipt(Some(shares)), &key)] {
+            assert!(ReceiptSignature::verify(&receipt, &public_key).is_ok());
+        }
+
+        // Settlement-side fields are outside the payload
+        let mut settled = signed(receipt(None), &key);
+        settled.settlement_status = SettlementStatus::Completed;
+        settled.settled_at = Some(43);
+        assert!(ReceiptSignature::verify(&settled, &public_key).is_ok());
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes();
+        assert!(ReceiptSignature::verify(&settled, &other).is_err());
+        assert!(ReceiptSignature::verify(&receipt(None), &public_key).is_err());
+    }
+
+    #[test]
+    fn tampered_fields_are_rejected() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let original = signed(receipt(Some(vec![PayoutShare { agent_id: "agent_2".to_string(), share: ShareSpec::Amount(500) }])), &key);
+
+        let tampers: Vec<fn(&mut Receipt)> = vec![
+            |r| r.actual_cost += 1,
+            |r| r.agent_id = "agent_9".to_string(),
+            |r| r.fees_breakdown.protocol_fee = 0,
+            |r| r.close_escrow = Some(false),
+            |r| r.shares.as_mut().unwrap()[0].share = ShareSpec::Weight(500),
+        ];
+        for tamper in tampers {
+            let mut receipt = original.clone();
+            tamper(&mut receipt);
+            assert!(ReceiptSignature::verify(&receipt, &public_key).is_err());
+        }
+    }
+
+    #[test]
+    fn payload_keys_are_in_canonical_order() {
+        let keys = ["job_id", "shares", "agent_id", "escrow_id", "created_at", "receipt_id", "actual_cost", "close_escrow", "fees_breakdown"];
+        let mut canonical = keys;
+        canonical.sort_by(|a, b| a.len().cmp(&b.len()).then(a.as_bytes().cmp(b.as_bytes())));
+        assert_eq!(keys, canonical);
+
+        // Single-agent payload, so every key below occurs once
+        let payload = ReceiptSignature::signing_payload(&receipt(None)).unwrap();
+        assert!(payload.starts_with(DOMAIN_SEPARATOR));
+        let positions: Vec<usize> = keys.iter().filter(|key| **key != "shares").map(|key| position(&payload, key)).collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", positions);
+        let fee_keys: Vec<usize> = ["agent_fee", "base_amount", "protocol_fee", "total_amount"].iter().map(|key| position(&payload, key)).collect();
+        assert!(fee_keys.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", fee_keys);
+
+        // Encoding is deterministic
+        assert_eq!(payload, ReceiptSignature::signing_payload(&receipt(None)).unwrap());
+    }
+}
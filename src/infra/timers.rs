@@ -0,0 +1,36 @@
+use ic_cdk_timers::TimerId;
+use std::cell::RefCell;
+use std::time::Duration;
+
+/// Interval timer driving one periodic job. Timers do not survive upgrades, so the handle lives
+/// in a thread-local rather than stable state and jobs are re-armed from `init` and `post_upgrade`.
+pub struct PeriodicTimer(RefCell<Option<TimerId>>);
+
+impl PeriodicTimer {
+    pub const fn new() -> Self {
+        Self(RefCell::new(None))
+    }
+
+    /// Run `job` every `interval`, replacing any timer already armed
+    pub fn start(&self, interval: Duration, job: impl FnMut() + 'static) {
+        self.stop();
+        let timer_id = ic_cdk_timers::set_timer_interval(interval, job);
+        *self.0.borrow_mut() = Some(timer_id);
+    }
+
+    pub fn stop(&self) {
+        if let Some(timer_id) = self.0.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(timer_id);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+}
+
+impl Default for PeriodicTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
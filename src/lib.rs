@@ -4,31 +4,34 @@ pub mod services;
 pub mod infra;
 
 // Re-export main types and functions
-pub use api::*;
 pub use domain::*;
 pub use services::*;
 pub use infra::*;
 
 use ic_cdk_macros::{init, pre_upgrade, post_upgrade};
-use ic_cdk::api::stable::StableMemoryError;
 use ic_cdk::api::caller;
 use candid::Principal;
 
 #[init]
 fn init() {
-    // Initialize default fee policy and grant the installer as admin
+    // Initialize default fee policy and tier catalog, and grant the installer as admin
     let installer = caller();
     services::with_state_mut(|state| {
-        if state.fee_policy.last_updated == 0 {
-            state.fee_policy = domain::FeePolicy::default();
+        if state.fee_policy.is_none() {
+            state.fee_policy = Some(domain::FeePolicy::default());
+        }
+        if state.tier_catalog.is_none() {
+            state.tier_catalog = Some(services::SubscriptionService::default_tier_catalog());
         }
         if let Some(text) = principal_to_text(&installer) {
             if !state.admins.iter().any(|p| p == &text) {
                 state.admins.push(text);
             }
         }
-        state.state_version = 1;
+        state.state_version = 4;
     });
+    services::SweeperService::start();
+    services::BillingService::start();
 }
 
 #[pre_upgrade]
@@ -53,12 +56,50 @@ fn post_upgrade() {
                     // This is best-effort and safe to be empty if not derivable
                 }
             }
+            if restored.state_version == 1 {
+                // Seed the double-entry journal from the pre-journal balances
+                if let Err(e) = services::JournalService::open_from_balances(&mut restored) {
+                    ic_cdk::trap(&format!("Failed to open journal: {}", e));
+                }
+                restored.state_version = 2;
+            }
+            if restored.state_version == 2 {
+                // Fee rates moved from f32 percentages to basis points; carry the old values over
+                if restored.fee_policy.is_none() {
+                    let legacy = ic_cdk::storage::stable_restore::<(LegacyFeeState,)>()
+                        .map(|(legacy,)| legacy.fee_policy.to_fee_policy())
+                        .unwrap_or_default();
+                    restored.fee_policy = Some(legacy);
+                }
+                restored.state_version = 3;
+            }
+            if restored.state_version == 3 {
+                // Tiers moved from code into state; seed the catalog and link subscribers to it
+                if restored.tier_catalog.is_none() {
+                    restored.tier_catalog = Some(services::SubscriptionService::default_tier_catalog());
+                }
+                for subscription in restored.subscriptions.values_mut() {
+                    if subscription.tier_id.is_none() {
+                        subscription.tier_id = Some(subscription.tier.name.to_lowercase());
+                    }
+                }
+                restored.state_version = 4;
+            }
             services::set_state(restored);
         }
         Err(_) => {
             // Fresh install or corrupted state; keep defaults
         }
     }
+    // Timers are dropped on upgrade
+    services::SweeperService::start();
+    services::BillingService::start();
+}
+
+/// The part of pre-version-3 stable state holding the percentage-based fee policy
+#[derive(serde::Deserialize, candid::CandidType)]
+struct LegacyFeeState {
+    fee_policy: domain::LegacyFeePolicy,
 }
 
 fn principal_to_text(p: &Principal) -> Option<String> {
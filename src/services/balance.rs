@@ -1,6 +1,54 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut};
-use ic_cdk::api::time;
+use crate::services::{with_state, with_state_mut, JournalService};
+use crate::infra::{InFlightLock, LedgerClient};
+use crate::infra::ledger::{
+    describe_transfer_error, describe_transfer_from_error, nat_to_u64, Account, TransferArg, TransferError,
+    TransferFromArgs, TransferFromError,
+};
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Digest};
+use std::collections::HashMap;
+use crate::infra::runtime::time;
+
+/// Outgoing ledger transfer awaiting a definite answer from the ledger. Withdrawals are debited
+/// before they are sent; deposit sweeps are credited once the ledger confirms them.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PendingTransfer {
+    pub reference: String,
+    pub principal_id: String,
+    pub kind: PendingTransferKind,
+    pub arg: TransferArg,
+    // Journal amount; a withdrawal sends it minus the ledger fee
+    pub amount: u64,
+    pub created_at: u64,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, CandidType)]
+pub enum PendingTransferKind {
+    Withdrawal,
+    DepositSweep,
+}
+
+/// ICRC-2 pull from a user's allowance, kept from the first attempt until the flow that needed the
+/// funds has used them. The journal is credited once, when the ledger first confirms the pull.
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct PendingPull {
+    pub reference: String,
+    pub principal_id: String,
+    pub args: TransferFromArgs,
+    pub amount: u64,
+    // Account credited from External once the pull executes; None when the funds leave custody
+    pub credit_to: Option<JournalAccount>,
+    pub kind: JournalEntryKind,
+    pub created_at: u64,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    // Set once the ledger confirmed the pull and the journal was credited
+    pub block_index: Option<u64>,
+}
 
 pub struct BalanceService;
 
@@ -11,65 +59,447 @@ impl BalanceService {
                 Ok(balance.clone())
             } else {
                 // Create default balance for new user
-                Ok(Balance {
-                    principal_id: principal_id.to_string(),
-                    available_balance: 0,
-                    escrowed_balance: 0,
-                    total_earnings: 0,
-                    last_updated: time(),
-                })
+                Ok(Balance::empty(principal_id, time()))
             }
         })
     }
     
-    pub fn deposit(principal_id: String, amount: u64) -> Result<(), String> {
-        let now = time();
+    /// Credit whatever has arrived in the principal's deposit subaccount since the last sync.
+    /// The funds are swept into the canister's main account first, so only tokens that actually
+    /// moved are credited, net of the sweep fee. A sweep whose ledger call was rejected is retried
+    /// before anything new is observed.
+    pub async fn sync_deposit(principal: Principal) -> Result<u64, String> {
+        let principal_id = principal.to_text();
+        let _lock = InFlightLock::acquire(format!("deposit:{}", principal_id))?;
+        
+        let (recovered, unresolved) = Self::reconcile_transfers(&principal_id, PendingTransferKind::DepositSweep).await;
+        if unresolved {
+            // The unresolved sweep may still be part of the subaccount balance
+            return Err("A previous deposit sweep is still unresolved; retry later".to_string());
+        }
         
+        let subaccount = LedgerClient::principal_subaccount(&principal);
+        let observed = LedgerClient::balance_of(LedgerClient::deposit_account(&principal)).await?;
+        let fee = LedgerClient::fee().await?;
+        if observed <= fee {
+            return Ok(recovered);
+        }
+        
+        let amount = observed - fee;
+        let now = time();
+        let reference = format!("deposit:{}:{}", principal_id, now);
+        let pending = PendingTransfer {
+            reference: reference.clone(),
+            principal_id,
+            kind: PendingTransferKind::DepositSweep,
+            arg: TransferArg {
+                from_subaccount: Some(subaccount.to_vec()),
+                to: LedgerClient::canister_account(None),
+                amount: Nat::from(amount),
+                fee: Some(Nat::from(fee)),
+                memo: Some(Self::transfer_memo(&reference)),
+                created_at_time: Some(now),
+            },
+            amount,
+            created_at: now,
+            attempts: 0,
+            last_error: None,
+        };
         with_state_mut(|state| {
-            let balance = state.balances.entry(principal_id.clone()).or_insert_with(|| Balance {
-                principal_id: principal_id.clone(),
-                available_balance: 0,
-                escrowed_balance: 0,
-                total_earnings: 0,
-                last_updated: now,
-            });
-            
-            balance.available_balance += amount;
-            balance.last_updated = now;
+            state.pending_transfers.get_or_insert_with(HashMap::new).insert(reference, pending.clone());
         });
         
+        Self::attempt_transfer(pending).await?;
+        Ok(recovered + amount)
+    }
+    
+    fn credit_deposit(principal_id: String, amount: u64, reference: &str) -> Result<(), String> {
+        with_state_mut(|state| {
+            JournalService::transfer(
+                state,
+                JournalEntryKind::Deposit,
+                reference,
+                JournalAccount::External,
+                JournalAccount::UserAvailable(principal_id.clone()),
+                amount,
+            )
+        })?;
+        
         Ok(())
     }
     
-    pub fn withdraw(principal_id: String, amount: u64) -> Result<(), String> {
+    /// Debit the balance and send the tokens out through the ledger. The balance is restored only
+    /// when the ledger definitely refuses the transfer; if the call is rejected the debit stays
+    /// pending until a later withdrawal reconciles it. The recipient receives `amount` minus the
+    /// ledger fee.
+    pub async fn withdraw(principal_id: String, amount: u64, to: Account) -> Result<u64, String> {
+        // One withdrawal per principal at a time across the ledger await
+        let _lock = InFlightLock::acquire(format!("withdraw:{}", principal_id))?;
+        
+        Self::reconcile_transfers(&principal_id, PendingTransferKind::Withdrawal).await;
+        
+        let fee = LedgerClient::fee().await?;
+        if amount <= fee {
+            return Err(format!("Withdrawal must exceed the ledger fee of {}", fee));
+        }
+        
         let now = time();
+        let reference = format!("withdraw:{}:{}", principal_id, now);
+        let pending = PendingTransfer {
+            reference: reference.clone(),
+            principal_id: principal_id.clone(),
+            kind: PendingTransferKind::Withdrawal,
+            arg: TransferArg {
+                from_subaccount: None,
+                to,
+                amount: Nat::from(amount - fee),
+                fee: Some(Nat::from(fee)),
+                memo: Some(Self::transfer_memo(&reference)),
+                created_at_time: Some(now),
+            },
+            amount,
+            created_at: now,
+            attempts: 0,
+            last_error: None,
+        };
+        with_state_mut(|state| {
+            let mut postings = JournalService::spend_postings(state, &principal_id, amount)?;
+            postings.push(Posting::credit(JournalAccount::External, amount));
+            JournalService::post(state, JournalEntryKind::Withdrawal, &reference, postings)?;
+            state.pending_transfers.get_or_insert_with(HashMap::new).insert(reference, pending.clone());
+            Ok::<(), String>(())
+        })?;
+        
+        Self::attempt_transfer(pending).await
+    }
+    
+    /// Retry the principal's pending transfers of `kind`. Returns the amount credited by recovered
+    /// deposit sweeps and whether any transfer is still unresolved.
+    async fn reconcile_transfers(principal_id: &str, kind: PendingTransferKind) -> (u64, bool) {
+        let pending: Vec<PendingTransfer> = with_state(|state| {
+            state.pending_transfers.as_ref()
+                .map(|transfers| transfers.values()
+                    .filter(|transfer| transfer.principal_id == principal_id && transfer.kind == kind)
+                    .cloned()
+                    .collect())
+                .unwrap_or_default()
+        });
         
+        let (mut recovered, mut unresolved) = (0u64, false);
+        for transfer in pending {
+            let (reference, amount) = (transfer.reference.clone(), transfer.amount);
+            match Self::attempt_transfer(transfer).await {
+                Ok(_) if kind == PendingTransferKind::DepositSweep => recovered += amount,
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("Pending transfer {} not settled: {}", reference, e);
+                    unresolved |= Self::get_pending_transfer(&reference).is_some();
+                }
+            }
+        }
+        (recovered, unresolved)
+    }
+    
+    /// Send a pending transfer and settle it once the ledger gives a definite answer. Retries reuse
+    /// the original `created_at_time` and memo, so a transfer that already executed comes back as
+    /// a duplicate of its block instead of moving funds twice.
+    async fn attempt_transfer(transfer: PendingTransfer) -> Result<u64, String> {
+        let result = match LedgerClient::try_transfer(transfer.arg.clone()).await {
+            Ok(Ok(block_index)) => Ok(block_index),
+            Ok(Err(TransferError::Duplicate { duplicate_of })) => nat_to_u64(&duplicate_of),
+            // Past the deduplication window the ledger can no longer tell whether the original executed
+            Ok(Err(TransferError::TooOld)) => return Self::keep_pending(&transfer.reference, "Transfer too old to deduplicate; an admin must resolve it".to_string()),
+            Ok(Err(e)) => Err(describe_transfer_error(&e)),
+            Err(e) => return Self::keep_pending(&transfer.reference, e),
+        };
+        
+        Self::settle_transfer(transfer, result)
+    }
+    
+    /// Drop a transfer the ledger has answered, crediting a confirmed sweep and restoring a
+    /// refused withdrawal
+    fn settle_transfer(transfer: PendingTransfer, result: Result<u64, String>) -> Result<u64, String> {
+        with_state_mut(|state| {
+            if let Some(transfers) = state.pending_transfers.as_mut() {
+                transfers.remove(&transfer.reference);
+            }
+        });
+        
+        match (result, transfer.kind) {
+            (Ok(block_index), PendingTransferKind::DepositSweep) => {
+                Self::credit_deposit(transfer.principal_id, transfer.amount, &format!("block:{}", block_index))?;
+                Ok(block_index)
+            }
+            (Ok(block_index), PendingTransferKind::Withdrawal) => Ok(block_index),
+            (Err(e), PendingTransferKind::DepositSweep) => Err(format!("Deposit sweep failed: {}", e)),
+            (Err(e), PendingTransferKind::Withdrawal) => {
+                // The ledger refused the transfer, so nothing left the canister: put the funds back
+                Self::reverse_withdrawal(&transfer)?;
+                Err(format!("Withdrawal failed: {}", e))
+            }
+        }
+    }
+    
+    fn reverse_withdrawal(transfer: &PendingTransfer) -> Result<(), String> {
         with_state_mut(|state| {
-            if let Some(balance) = state.balances.get_mut(&principal_id) {
-                if balance.available_balance < amount {
-                    return Err("Insufficient balance".to_string());
+            JournalService::transfer(
+                state,
+                JournalEntryKind::WithdrawalReversal,
+                &transfer.reference,
+                JournalAccount::External,
+                JournalAccount::UserAvailable(transfer.principal_id.clone()),
+                transfer.amount,
+            )
+        })?;
+        Ok(())
+    }
+    
+    /// Settle a transfer the ledger can no longer deduplicate, after checking the ledger by hand.
+    /// `Some(block_index)` settles it as executed; `None` treats it as never sent, so a withdrawal
+    /// is returned to the balance and a sweep's funds are picked up by the next deposit sync.
+    pub fn resolve_pending_transfer(reference: String, block_index: Option<u64>) -> Result<(), String> {
+        let transfer = Self::get_pending_transfer(&reference).ok_or_else(|| format!("No pending transfer {}", reference))?;
+        match block_index {
+            Some(block_index) => Self::settle_transfer(transfer, Ok(block_index)).map(|_| ()),
+            None => {
+                with_state_mut(|state| {
+                    if let Some(transfers) = state.pending_transfers.as_mut() {
+                        transfers.remove(&reference);
+                    }
+                });
+                if transfer.kind == PendingTransferKind::Withdrawal {
+                    Self::reverse_withdrawal(&transfer)?;
                 }
-                
-                balance.available_balance -= amount;
-                balance.last_updated = now;
                 Ok(())
-            } else {
-                Err("Balance not found".to_string())
             }
+        }
+    }
+    
+    fn keep_pending(reference: &str, error: String) -> Result<u64, String> {
+        with_state_mut(|state| {
+            if let Some(transfer) = state.pending_transfers.as_mut().and_then(|transfers| transfers.get_mut(reference)) {
+                transfer.attempts += 1;
+                transfer.last_error = Some(error.clone());
+            }
+        });
+        Err(format!("Ledger outcome unknown, transfer {} stays pending: {}", reference, error))
+    }
+    
+    fn get_pending_transfer(reference: &str) -> Option<PendingTransfer> {
+        with_state(|state| state.pending_transfers.as_ref().and_then(|transfers| transfers.get(reference).cloned()))
+    }
+    
+    pub fn list_pending_transfers(principal_id: Option<String>) -> Vec<PendingTransfer> {
+        with_state(|state| {
+            let mut transfers: Vec<PendingTransfer> = state.pending_transfers.as_ref()
+                .map(|transfers| transfers.values()
+                    .filter(|transfer| principal_id.as_ref().is_none_or(|p| &transfer.principal_id == p))
+                    .cloned()
+                    .collect())
+                .unwrap_or_default();
+            transfers.sort_by_key(|transfer| transfer.created_at);
+            transfers
+        })
+    }
+    
+    /// Pull `amount` from `from` under a reference that is fixed for the caller's intent. An
+    /// existing pull for the reference is retried with its original arguments, so one that already
+    /// executed comes back as a duplicate and is credited only once. Returns the block index; the
+    /// caller calls `complete_pull` once it has used the funds.
+    pub async fn pull(
+        reference: &str,
+        from: Principal,
+        to: Account,
+        amount: u64,
+        credit_to: Option<JournalAccount>,
+        kind: JournalEntryKind,
+    ) -> Result<u64, String> {
+        let _lock = InFlightLock::acquire(format!("pull:{}", reference))?;
+        
+        let pull = match Self::get_pending_pull(reference) {
+            Some(pull) => pull,
+            None => {
+                let now = time();
+                let pull = PendingPull {
+                    reference: reference.to_string(),
+                    principal_id: from.to_text(),
+                    args: TransferFromArgs {
+                        spender_subaccount: None,
+                        from: Account { owner: from, subaccount: None },
+                        to,
+                        amount: Nat::from(amount),
+                        fee: None,
+                        memo: Some(Self::transfer_memo(reference)),
+                        created_at_time: Some(now),
+                    },
+                    amount,
+                    credit_to,
+                    kind,
+                    created_at: now,
+                    attempts: 0,
+                    last_error: None,
+                    block_index: None,
+                };
+                with_state_mut(|state| {
+                    state.pending_pulls.get_or_insert_with(HashMap::new).insert(reference.to_string(), pull.clone());
+                });
+                pull
+            }
+        };
+        
+        if let Some(block_index) = pull.block_index {
+            return Ok(block_index);
+        }
+        
+        let amount = pull.amount;
+        let result = match LedgerClient::try_transfer_from(pull.args.clone()).await {
+            Ok(Ok(block_index)) => block_index,
+            Ok(Err(TransferFromError::Duplicate { duplicate_of })) => nat_to_u64(&duplicate_of)?,
+            Ok(Err(TransferFromError::TooOld)) => return Self::keep_pull_pending(reference, "Pull too old to deduplicate; needs manual review".to_string()),
+            Ok(Err(e)) => {
+                // A definite refusal: nothing moved, so the pull can be forgotten
+                with_state_mut(|state| {
+                    if let Some(pulls) = state.pending_pulls.as_mut() {
+                        pulls.remove(reference);
+                    }
+                });
+                return Err(describe_transfer_from_error(&e, amount));
+            }
+            Err(e) => return Self::keep_pull_pending(reference, e),
+        };
+        
+        Self::settle_pull(reference, result)
+    }
+    
+    /// Record a confirmed pull and credit the journal, once
+    fn settle_pull(reference: &str, block_index: u64) -> Result<u64, String> {
+        with_state_mut(|state| {
+            let pull = state.pending_pulls.as_ref()
+                .and_then(|pulls| pulls.get(reference))
+                .cloned()
+                .ok_or_else(|| format!("No pending pull {}", reference))?;
+            if let Some(settled) = pull.block_index {
+                return Ok(settled);
+            }
+            if let Some(account) = pull.credit_to.clone() {
+                JournalService::transfer(
+                    state,
+                    pull.kind.clone(),
+                    &format!("block:{}", block_index),
+                    JournalAccount::External,
+                    account,
+                    pull.amount,
+                )?;
+            }
+            if let Some(pull) = state.pending_pulls.as_mut().and_then(|pulls| pulls.get_mut(reference)) {
+                pull.block_index = Some(block_index);
+                pull.last_error = None;
+            }
+            Ok(block_index)
+        })
+    }
+    
+    /// Forget a confirmed pull once the flow that requested it has used the funds
+    pub fn complete_pull(reference: &str) {
+        with_state_mut(|state| {
+            if let Some(pulls) = state.pending_pulls.as_mut() {
+                pulls.remove(reference);
+            }
+        });
+    }
+    
+    /// Settle a pull the ledger can no longer deduplicate, after checking the ledger by hand.
+    /// `Some(block_index)` credits it as executed; `None` drops it as never executed.
+    pub fn resolve_pending_pull(reference: String, block_index: Option<u64>) -> Result<(), String> {
+        let pull = Self::get_pending_pull(&reference).ok_or_else(|| format!("No pending pull {}", reference))?;
+        if pull.block_index.is_some() {
+            return Err("Pull already executed; it is waiting to be used".to_string());
+        }
+        match block_index {
+            Some(block_index) => Self::settle_pull(&reference, block_index).map(|_| ()),
+            None => {
+                Self::complete_pull(&reference);
+                Ok(())
+            }
+        }
+    }
+    
+    fn keep_pull_pending(reference: &str, error: String) -> Result<u64, String> {
+        with_state_mut(|state| {
+            if let Some(pull) = state.pending_pulls.as_mut().and_then(|pulls| pulls.get_mut(reference)) {
+                pull.attempts += 1;
+                pull.last_error = Some(error.clone());
+            }
+        });
+        Err(format!("Ledger outcome unknown, pull {} stays pending; retry the same request: {}", reference, error))
+    }
+    
+    pub fn get_pending_pull(reference: &str) -> Option<PendingPull> {
+        with_state(|state| state.pending_pulls.as_ref().and_then(|pulls| pulls.get(reference).cloned()))
+    }
+    
+    pub fn list_pending_pulls(principal_id: Option<String>) -> Vec<PendingPull> {
+        with_state(|state| {
+            let mut pulls: Vec<PendingPull> = state.pending_pulls.as_ref()
+                .map(|pulls| pulls.values()
+                    .filter(|pull| principal_id.as_ref().is_none_or(|p| &pull.principal_id == p))
+                    .cloned()
+                    .collect())
+                .unwrap_or_default();
+            pulls.sort_by_key(|pull| pull.created_at);
+            pulls
         })
     }
     
+    fn transfer_memo(reference: &str) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(reference.as_bytes());
+        hasher.finalize().to_vec()
+    }
+    
     pub fn get_fee_policy() -> FeePolicy {
-        with_state(|state| state.fee_policy.clone())
+        with_state(|state| state.fee_policy())
     }
     
     pub fn update_fee_policy(new_policy: FeePolicy) -> Result<(), String> {
         let now = time();
+        new_policy.validate()?;
         
         with_state_mut(|state| {
+            // Keep the outgoing policy so escrows opened under it settle against the same rates
+            let version = state.fee_policy_version();
+            let previous = state.fee_policy();
+            state.fee_policy_history.get_or_insert_with(HashMap::new).insert(version, previous);
+            
             let mut updated_policy = new_policy;
             updated_policy.last_updated = now;
-            state.fee_policy = updated_policy;
+            state.fee_policy = Some(updated_policy);
+            state.fee_policy_version = Some(version + 1);
+        });
+        
+        Ok(())
+    }
+    
+    pub fn get_fee_policy_version() -> u32 {
+        with_state(|state| state.fee_policy_version())
+    }
+    
+    pub fn get_fee_policy_at(version: u32) -> Option<FeePolicy> {
+        with_state(|state| state.fee_policy_at(version))
+    }
+    
+    pub fn get_fee_tolerance() -> FeeTolerance {
+        with_state(|state| state.fee_tolerance.clone().unwrap_or_default())
+    }
+    
+    pub fn set_fee_tolerance(tolerance: FeeTolerance) -> Result<(), String> {
+        if tolerance.tolerance_bps > BasisPoints::ONE_HUNDRED_PERCENT.value() {
+            return Err("Fee tolerance cannot exceed 100%".to_string());
+        }
+        
+        with_state_mut(|state| {
+            state.fee_tolerance = Some(tolerance);
+            state.metrics.last_activity = time();
         });
         
         Ok(())
@@ -95,6 +525,20 @@ impl BalanceService {
                 0.0
             };
             
+            let open_disputes = state.receipts
+                .values()
+                .filter(|receipt| matches!(receipt.settlement_status, SettlementStatus::Disputed))
+                .count() as u32;
+            let resolved_disputes = state.receipts
+                .values()
+                .filter(|receipt| receipt.dispute.as_ref().is_some_and(|dispute| dispute.resolution.is_some()))
+                .count() as u32;
+            let held_payouts = state.receipts
+                .values()
+                .filter_map(|receipt| receipt.payout_hold.as_ref())
+                .filter(|hold| hold.released_at.is_none())
+                .fold(0u64, |total, hold| total.saturating_add(hold.amount));
+            
             EconHealth {
                 total_escrows,
                 active_escrows,
@@ -103,7 +547,251 @@ impl BalanceService {
                 total_volume: state.metrics.total_volume,
                 protocol_fees_collected: state.metrics.protocol_fees_collected,
                 average_job_cost,
+                open_disputes,
+                resolved_disputes,
+                held_payouts,
             }
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::mock_ledger::{MockFault, MockLedger};
+    use crate::infra::runtime::{self, block_on};
+
+    fn user(id: u8) -> Principal {
+        Principal::from_slice(&[id; 10])
+    }
+
+    fn wallet(id: u8) -> Account {
+        Account { owner: user(id), subaccount: None }
+    }
+
+    fn ledger_balance(account: Account) -> u64 {
+        nat_to_u64(&MockLedger::icrc1_balance_of(account)).unwrap()
+    }
+
+    fn available(principal: Principal) -> u64 {
+        BalanceService::get_balance(&principal.to_text()).unwrap().available_balance
+    }
+
+    fn setup() {
+        MockLedger::reset();
+        runtime::set_time(1_000_000_000);
+    }
+
+    fn deposit(principal: Principal, amount: u64) -> u64 {
+        MockLedger::mint(LedgerClient::deposit_account(&principal), amount);
+        let credited = block_on(BalanceService::sync_deposit(principal)).unwrap();
+        runtime::advance_time(1);
+        credited
+    }
+
+    fn withdraw(principal: Principal, amount: u64, to: u8) -> Result<u64, String> {
+        let result = block_on(BalanceService::withdraw(principal.to_text(), amount, wallet(to)));
+        runtime::advance_time(1);
+        result
+    }
+
+    #[test]
+    fn sync_deposit_credits_only_newly_arrived_funds() {
+        setup();
+        assert_eq!(deposit(user(1), 1_000_000), 990_000);
+        assert_eq!(available(user(1)), 990_000);
+        assert_eq!(ledger_balance(LedgerClient::canister_account(None)), 990_000);
+        assert_eq!(ledger_balance(LedgerClient::deposit_account(&user(1))), 0);
+
+        // Nothing new, then dust that would not cover the sweep fee
+        assert_eq!(deposit(user(1), 0), 0);
+        assert_eq!(deposit(user(1), 5_000), 0);
+        assert_eq!(available(user(1)), 990_000);
+        assert!(JournalService::reconcile().conserved);
+    }
+
+    #[test]
+    fn deposit_sweep_with_lost_reply_is_credited_once() {
+        setup();
+        MockLedger::mint(LedgerClient::deposit_account(&user(1)), 1_000_000);
+        MockLedger::inject_fault(MockFault::LoseReply);
+        assert!(block_on(BalanceService::sync_deposit(user(1))).is_err());
+        assert_eq!(available(user(1)), 0);
+        assert_eq!(BalanceService::list_pending_transfers(None).len(), 1);
+
+        runtime::advance_time(1);
+        assert_eq!(block_on(BalanceService::sync_deposit(user(1))).unwrap(), 990_000);
+        assert_eq!(available(user(1)), 990_000);
+        assert_eq!(ledger_balance(LedgerClient::canister_account(None)), 990_000);
+        assert!(BalanceService::list_pending_transfers(None).is_empty());
+    }
+
+    #[test]
+    fn withdraw_sends_amount_net_of_fee() {
+        setup();
+        deposit(user(1), 1_000_000);
+
+        withdraw(user(1), 500_000, 2).unwrap();
+        assert_eq!(ledger_balance(wallet(2)), 490_000);
+        assert_eq!(available(user(1)), 490_000);
+        assert_eq!(ledger_balance(LedgerClient::canister_account(None)), 490_000);
+
+        assert!(withdraw(user(1), 600_000, 2).is_err());
+        assert!(withdraw(user(1), 10_000, 2).is_err());
+        assert_eq!(available(user(1)), 490_000);
+        assert!(JournalService::reconcile().conserved);
+    }
+
+    #[test]
+    fn withdraw_refused_by_ledger_restores_balance() {
+        setup();
+        // Credited in the journal but never backed on the ledger
+        with_state_mut(|state| JournalService::transfer(
+            state,
+            JournalEntryKind::Deposit,
+            "unbacked",
+            JournalAccount::External,
+            JournalAccount::UserAvailable(user(1).to_text()),
+            100_000,
+        )).unwrap();
+
+        let error = withdraw(user(1), 50_000, 2).unwrap_err();
+        assert!(error.starts_with("Withdrawal failed"), "{}", error);
+        assert_eq!(available(user(1)), 100_000);
+        assert!(BalanceService::list_pending_transfers(None).is_empty());
+    }
+
+    #[test]
+    fn rejected_withdrawal_stays_debited_until_retried() {
+        setup();
+        deposit(user(1), 1_000_000);
+
+        MockLedger::inject_fault(MockFault::Reject);
+        assert!(withdraw(user(1), 500_000, 2).is_err());
+        assert_eq!(available(user(1)), 490_000);
+        assert_eq!(ledger_balance(wallet(2)), 0);
+        assert_eq!(BalanceService::list_pending_transfers(None).len(), 1);
+
+        // The next withdrawal first sends the pending one
+        withdraw(user(1), 100_000, 2).unwrap();
+        assert_eq!(ledger_balance(wallet(2)), 490_000 + 90_000);
+        assert_eq!(available(user(1)), 390_000);
+        assert!(BalanceService::list_pending_transfers(None).is_empty());
+    }
+
+    #[test]
+    fn withdrawal_with_lost_reply_is_not_sent_twice() {
+        setup();
+        deposit(user(1), 1_000_000);
+
+        MockLedger::inject_fault(MockFault::LoseReply);
+        assert!(withdraw(user(1), 500_000, 2).is_err());
+        assert_eq!(ledger_balance(wallet(2)), 490_000);
+
+        withdraw(user(1), 100_000, 2).unwrap();
+        assert_eq!(ledger_balance(wallet(2)), 490_000 + 90_000);
+        assert_eq!(available(user(1)), 390_000);
+        assert!(BalanceService::list_pending_transfers(None).is_empty());
+        assert!(JournalService::reconcile().conserved);
+    }
+
+    fn approve(principal: Principal, amount: u64) {
+        MockLedger::mint(Account { owner: principal, subaccount: None }, amount + 10_000);
+        MockLedger::icrc2_approve(principal, crate::infra::ledger::ApproveArgs {
+            from_subaccount: None,
+            spender: LedgerClient::canister_account(None),
+            amount: Nat::from(amount),
+            expected_allowance: None,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }).unwrap();
+    }
+
+    fn pull(reference: &str, principal: Principal, amount: u64) -> Result<u64, String> {
+        let result = block_on(BalanceService::pull(
+            reference,
+            principal,
+            LedgerClient::canister_account(None),
+            amount,
+            Some(JournalAccount::UserAvailable(principal.to_text())),
+            JournalEntryKind::Deposit,
+        ));
+        runtime::advance_time(1);
+        result
+    }
+
+    #[test]
+    fn pull_with_lost_reply_is_credited_once() {
+        setup();
+        approve(user(1), 1_000_000);
+
+        MockLedger::inject_fault(MockFault::LoseReply);
+        assert!(pull("job", user(1), 300_000).is_err());
+        assert_eq!(available(user(1)), 0);
+        assert_eq!(BalanceService::list_pending_pulls(None).len(), 1);
+
+        // The retry resends the same pull, which the ledger reports as a duplicate
+        let block = pull("job", user(1), 300_000).unwrap();
+        assert_eq!(available(user(1)), 300_000);
+        assert_eq!(ledger_balance(wallet(1)), 1_000_000 - 300_000 - 10_000);
+
+        // Until the flow uses it, asking again returns the same block without crediting
+        assert_eq!(pull("job", user(1), 300_000).unwrap(), block);
+        assert_eq!(available(user(1)), 300_000);
+        BalanceService::complete_pull("job");
+        assert!(BalanceService::list_pending_pulls(None).is_empty());
+        assert!(JournalService::reconcile().conserved);
+    }
+
+    #[test]
+    fn refused_pull_is_forgotten() {
+        setup();
+        let error = pull("job", user(1), 300_000).unwrap_err();
+        assert!(error.contains("allowance"), "{}", error);
+        assert!(BalanceService::list_pending_pulls(None).is_empty());
+        assert_eq!(available(user(1)), 0);
+    }
+
+    #[test]
+    fn pending_pull_is_resolved_by_hand() {
+        setup();
+        approve(user(1), 1_000_000);
+        MockLedger::inject_fault(MockFault::Reject);
+        assert!(pull("job", user(1), 300_000).is_err());
+
+        BalanceService::resolve_pending_pull("job".to_string(), None).unwrap();
+        assert!(BalanceService::list_pending_pulls(None).is_empty());
+
+        MockLedger::inject_fault(MockFault::Reject);
+        assert!(pull("job", user(1), 300_000).is_err());
+        BalanceService::resolve_pending_pull("job".to_string(), Some(7)).unwrap();
+        assert_eq!(available(user(1)), 300_000);
+        assert_eq!(BalanceService::get_pending_pull("job").unwrap().block_index, Some(7));
+        assert!(BalanceService::resolve_pending_pull("job".to_string(), None).is_err());
+    }
+
+    #[test]
+    fn stuck_transfers_are_resolved_by_hand() {
+        setup();
+        deposit(user(1), 1_000_000);
+        MockLedger::inject_fault(MockFault::Reject);
+        assert!(withdraw(user(1), 500_000, 2).is_err());
+        let reference = BalanceService::list_pending_transfers(None)[0].reference.clone();
+
+        // Checked on the ledger and never sent: the debit is restored
+        BalanceService::resolve_pending_transfer(reference.clone(), None).unwrap();
+        assert_eq!(available(user(1)), 990_000);
+        assert!(BalanceService::list_pending_transfers(None).is_empty());
+        assert!(BalanceService::resolve_pending_transfer(reference, None).is_err());
+
+        // A stuck sweep no longer blocks deposits once settled as executed
+        MockLedger::mint(LedgerClient::deposit_account(&user(1)), 200_000);
+        MockLedger::inject_fault(MockFault::Reject);
+        assert!(block_on(BalanceService::sync_deposit(user(1))).is_err());
+        let sweep = BalanceService::list_pending_transfers(None)[0].reference.clone();
+        BalanceService::resolve_pending_transfer(sweep, Some(42)).unwrap();
+        assert_eq!(available(user(1)), 990_000 + 190_000);
+        assert!(BalanceService::list_pending_transfers(None).is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+use crate::domain::*;
+use crate::services::{with_state, with_state_mut, PaymentService, SubscriptionService};
+use crate::infra::InFlightLock;
+use crate::infra::runtime::time;
+use crate::infra::PeriodicTimer;
+use std::time::Duration;
+
+thread_local! {
+    static BILLING_TIMER: PeriodicTimer = const { PeriodicTimer::new() };
+}
+
+/// Periodic job renewing auto-renewing subscriptions as they near `expires_at`.
+/// Failed renewals are retried with backoff through the grace period, then suspended.
+/// Each run also starts new usage periods at the subscribers' calendar-month boundaries, and collects
+/// overage still owed on subscriptions that ended without renewing.
+pub struct BillingService;
+
+/// Outcome of a single renewal attempt
+enum RenewalOutcome {
+    Renewed,
+    Failed,
+    Suspended,
+}
+
+impl BillingService {
+    const BILLING_INTERVAL: Duration = Duration::from_secs(15 * 60);
+    const BATCH_SIZE: usize = 50;
+
+    /// Arm the periodic billing run; called from `init` and `post_upgrade`
+    pub fn start() {
+        BILLING_TIMER.with(|timer| timer.start(Self::BILLING_INTERVAL, || {
+            ic_cdk::spawn(async {
+                Self::run(SweepTrigger::Timer).await;
+            });
+        }));
+    }
+
+    pub fn stop() {
+        BILLING_TIMER.with(PeriodicTimer::stop);
+    }
+
+    pub fn is_running() -> bool {
+        BILLING_TIMER.with(PeriodicTimer::is_running)
+    }
+
+    /// Start new usage periods that have come due, attempt one batch of due renewals and record the
+    /// outcome. A timer run that leaves a backlog schedules another batch right away, as the sweeper does.
+    pub async fn run(trigger: SweepTrigger) -> BillingRunStatus {
+        let usage = SubscriptionService::roll_usage_periods(Self::BATCH_SIZE as u32);
+        let usage_resets = usage.rolled;
+        let due = Self::due_subscriptions(time());
+        let lapsed = Self::lapsed_with_overage(time());
+        let backlog = (due.len().saturating_sub(Self::BATCH_SIZE) as u32)
+            .saturating_add(usage.backlog)
+            .saturating_add(lapsed.len().saturating_sub(Self::BATCH_SIZE) as u32);
+
+        let (mut renewed, mut failed, mut suspended) = (0u32, 0u32, 0u32);
+        for principal_id in due.into_iter().take(Self::BATCH_SIZE) {
+            match Self::attempt_renewal(&principal_id).await {
+                RenewalOutcome::Renewed => renewed += 1,
+                RenewalOutcome::Failed => failed += 1,
+                RenewalOutcome::Suspended => suspended += 1,
+            }
+        }
+
+        let mut overage_collected = 0u32;
+        for principal_id in lapsed.into_iter().take(Self::BATCH_SIZE) {
+            match Self::collect_overage(&principal_id).await {
+                Ok(_) => overage_collected += 1,
+                Err(e) => Self::record_collection_failure(&principal_id, e),
+            }
+        }
+
+        let now = time();
+        let status = with_state_mut(|state| {
+            let previous = state.billing_status.as_ref();
+            let status = BillingRunStatus {
+                last_run_at: now,
+                last_trigger: trigger.clone(),
+                last_renewed: renewed,
+                last_failed: failed,
+                last_suspended: suspended,
+                backlog,
+                last_usage_resets: Some(usage_resets),
+                last_overage_collected: Some(overage_collected),
+                total_renewed: previous.map(|s| s.total_renewed).unwrap_or(0) + renewed as u64,
+                total_suspended: previous.map(|s| s.total_suspended).unwrap_or(0) + suspended as u64,
+                runs: previous.map(|s| s.runs).unwrap_or(0) + 1,
+            };
+            state.billing_status = Some(status.clone());
+            if renewed > 0 || suspended > 0 || usage_resets > 0 || overage_collected > 0 {
+                state.metrics.last_activity = now;
+            }
+            status
+        });
+
+        // Failed renewals back off before they are due again, so only real progress continues
+        let progress = renewed > 0 || suspended > 0 || usage_resets > 0 || overage_collected > 0;
+        if trigger == SweepTrigger::Timer && backlog > 0 && progress {
+            ic_cdk_timers::set_timer(Duration::ZERO, || {
+                ic_cdk::spawn(async {
+                    Self::run(SweepTrigger::Timer).await;
+                });
+            });
+        }
+
+        status
+    }
+
+    /// Renew a subscription now, charging the price of its next period
+    pub async fn renew(principal_id: &str) -> Result<Subscription, String> {
+        let _lock = InFlightLock::acquire(format!("billing:{}", principal_id))?;
+        let subscription = SubscriptionService::get_user_subscription(principal_id).ok_or("No subscription found")?;
+        let (tier_id, tier) = SubscriptionService::renewal_tier(&subscription);
+
+        // Accrued overage is invoiced together with the next period's fee
+        let overage_due = subscription.overage_due_e8s.unwrap_or(0);
+        let fee = if tier.monthly_fee_usd > 0 { PaymentService::usd_to_icp_e8s(tier.monthly_fee_usd)? } else { 0 };
+        let amount_icp_e8s = Money::from_e8s(fee).checked_add(Money::from_e8s(overage_due))?.e8s();
+        let overage_paid = if amount_icp_e8s > 0 {
+            let tier_label = tier_id.clone().unwrap_or_else(|| tier.name.to_lowercase());
+            let charged = PaymentService::charge_renewal(
+                principal_id,
+                &tier_label,
+                tier.monthly_fee_usd,
+                amount_icp_e8s,
+                subscription.expires_at,
+            ).await?;
+            // A retried pull charges what it was first sent for, which may predate newer overage
+            charged.amount_icp_e8s.saturating_sub(fee).min(overage_due)
+        } else {
+            0
+        };
+
+        SubscriptionService::complete_renewal(principal_id, tier_id, tier, overage_paid)
+    }
+
+    /// Auto-renewing, unsuspended subscriptions inside the renewal window whose retry is due,
+    /// oldest expiry first
+    fn due_subscriptions(now: u64) -> Vec<String> {
+        with_state(|state| {
+            let policy = state.billing_policy();
+            let mut due: Vec<(u64, String)> = state.subscriptions.values()
+                .filter(|subscription| subscription.auto_renew)
+                .filter(|subscription| !matches!(subscription.payment_status, PaymentStatus::Suspended | PaymentStatus::Cancelled))
+                .filter(|subscription| subscription.expires_at.saturating_sub(policy.renewal_lead) <= now)
+                .filter(|subscription| {
+                    subscription.billing.as_ref()
+                        .and_then(|billing| billing.next_attempt_at)
+                        .is_none_or(|next| next <= now)
+                })
+                .map(|subscription| (subscription.expires_at, subscription.principal_id.clone()))
+                .collect();
+            due.sort();
+            due.into_iter().map(|(_, principal_id)| principal_id).collect()
+        })
+    }
+
+    /// Subscriptions that ended without renewing while overage was still owed and whose collection
+    /// retry is due. Renewing subscriptions pay overage with their next period instead.
+    fn lapsed_with_overage(now: u64) -> Vec<String> {
+        with_state(|state| {
+            let mut lapsed: Vec<(u64, String)> = state.subscriptions.values()
+                .filter(|subscription| !subscription.auto_renew && subscription.expires_at <= now)
+                .filter(|subscription| subscription.overage_due_e8s.unwrap_or(0) > 0)
+                .filter(|subscription| {
+                    subscription.billing.as_ref()
+                        .and_then(|billing| billing.next_attempt_at)
+                        .is_none_or(|next| next <= now)
+                })
+                .map(|subscription| (subscription.expires_at, subscription.principal_id.clone()))
+                .collect();
+            lapsed.sort();
+            lapsed.into_iter().map(|(_, principal_id)| principal_id).collect()
+        })
+    }
+
+    /// Charge the overage still owed on a lapsed subscription
+    pub async fn collect_overage(principal_id: &str) -> Result<Subscription, String> {
+        let _lock = InFlightLock::acquire(format!("billing:{}", principal_id))?;
+        let subscription = SubscriptionService::get_user_subscription(principal_id).ok_or("No subscription found")?;
+        let overage_due = subscription.overage_due_e8s.unwrap_or(0);
+        if overage_due == 0 {
+            return Ok(subscription);
+        }
+
+        let tier_label = subscription.tier_id.clone().unwrap_or_else(|| subscription.tier.name.to_lowercase());
+        let charged = PaymentService::charge_overage(principal_id, &tier_label, overage_due, subscription.expires_at).await?;
+        SubscriptionService::settle_overage(principal_id, charged.amount_icp_e8s.min(overage_due))
+    }
+
+    /// Back off the next collection attempt; the debt stays visible as `overage_due_e8s`
+    fn record_collection_failure(principal_id: &str, error: String) {
+        let now = time();
+        with_state_mut(|state| {
+            let policy = state.billing_policy();
+            if let Some(subscription) = state.subscriptions.get_mut(principal_id) {
+                let billing = subscription.billing.get_or_insert_with(BillingState::default);
+                billing.attempts += 1;
+                billing.last_attempt_at = Some(now);
+                billing.last_error = Some(error);
+                billing.next_attempt_at = Some(now.saturating_add(policy.retry_after(billing.attempts)));
+                subscription.updated_at = now;
+            }
+        });
+    }
+
+    async fn attempt_renewal(principal_id: &str) -> RenewalOutcome {
+        match Self::renew(principal_id).await {
+            Ok(_) => RenewalOutcome::Renewed,
+            Err(e) => Self::record_failure(principal_id, e),
+        }
+    }
+
+    /// Schedule the next retry, entering the grace period once the subscription has expired and
+    /// suspending it once grace runs out
+    fn record_failure(principal_id: &str, error: String) -> RenewalOutcome {
+        let now = time();
+        with_state_mut(|state| {
+            let policy = state.billing_policy();
+            let Some(subscription) = state.subscriptions.get_mut(principal_id) else {
+                return RenewalOutcome::Failed;
+            };
+
+            let grace_ends_at = subscription.expires_at.saturating_add(policy.grace_period);
+            let billing = subscription.billing.get_or_insert_with(BillingState::default);
+            billing.attempts += 1;
+            billing.last_attempt_at = Some(now);
+            billing.last_error = Some(error);
+            billing.grace_ends_at = Some(grace_ends_at);
+            subscription.updated_at = now;
+
+            if now >= grace_ends_at {
+                billing.next_attempt_at = None;
+                billing.suspended_at = Some(now);
+                subscription.payment_status = PaymentStatus::Suspended;
+                return RenewalOutcome::Suspended;
+            }
+
+            // The last retry lands on the end of grace so suspension is never late
+            billing.next_attempt_at = Some(now.saturating_add(policy.retry_after(billing.attempts)).min(grace_ends_at));
+            if now >= subscription.expires_at {
+                subscription.payment_status = PaymentStatus::GracePeriod;
+            }
+            RenewalOutcome::Failed
+        })
+    }
+
+    pub fn get_billing_policy() -> BillingPolicy {
+        with_state(|state| state.billing_policy())
+    }
+
+    pub fn set_billing_policy(policy: BillingPolicy) -> Result<(), String> {
+        policy.validate()?;
+        with_state_mut(|state| {
+            state.billing_policy = Some(policy);
+            state.metrics.last_activity = time();
+        });
+        Ok(())
+    }
+
+    pub fn get_status() -> Option<BillingRunStatus> {
+        with_state(|state| state.billing_status.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::mock_ledger::MockLedger;
+    use crate::infra::runtime::{self, block_on};
+    use crate::services::JournalService;
+
+    fn user() -> String {
+        candid::Principal::from_slice(&[1; 10]).to_text()
+    }
+
+    fn lapsed_subscription(overage_due: u64) -> Subscription {
+        with_state_mut(|state| state.tier_catalog = Some(SubscriptionService::default_tier_catalog()));
+        let mut subscription = block_on(SubscriptionService::create_subscription(user(), "basic".to_string(), false)).unwrap();
+        subscription.auto_renew = false;
+        subscription.overage_due_e8s = Some(overage_due);
+        with_state_mut(|state| state.subscriptions.insert(user(), subscription.clone()));
+        runtime::set_time(subscription.expires_at + 1);
+        subscription
+    }
+
+    fn fund(amount: u64) {
+        with_state_mut(|state| JournalService::transfer(
+            state,
+            JournalEntryKind::Deposit,
+            "seed",
+            JournalAccount::External,
+            JournalAccount::UserAvailable(user()),
+            amount,
+        )).unwrap();
+    }
+
+    fn overage_due() -> u64 {
+        SubscriptionService::get_user_subscription(&user()).unwrap().overage_due_e8s.unwrap_or(0)
+    }
+
+    #[test]
+    fn lapsed_overage_is_collected_from_the_balance() {
+        MockLedger::reset();
+        runtime::set_time(1_000_000_000);
+        lapsed_subscription(500);
+        fund(1_000);
+
+        let status = block_on(BillingService::run(SweepTrigger::Manual));
+        assert_eq!(status.last_overage_collected, Some(1));
+        assert_eq!(overage_due(), 0);
+        assert_eq!(JournalService::account_balance(&JournalAccount::ProtocolTreasury), 500);
+        assert_eq!(JournalService::account_balance(&JournalAccount::UserAvailable(user())), 500);
+    }
+
+    #[test]
+    fn failed_collection_backs_off_and_keeps_the_debt() {
+        MockLedger::reset();
+        runtime::set_time(1_000_000_000);
+        lapsed_subscription(500);
+
+        let status = block_on(BillingService::run(SweepTrigger::Manual));
+        assert_eq!(status.last_overage_collected, Some(0));
+        assert_eq!(overage_due(), 500);
+        let billing = SubscriptionService::get_user_subscription(&user()).unwrap().billing.unwrap();
+        assert_eq!(billing.attempts, 1);
+        assert!(billing.last_error.is_some());
+
+        // Not retried until the backoff passes, then collected once funds arrive
+        fund(500);
+        block_on(BillingService::run(SweepTrigger::Manual));
+        assert_eq!(overage_due(), 500);
+        runtime::set_time(billing.next_attempt_at.unwrap());
+        block_on(BillingService::run(SweepTrigger::Manual));
+        assert_eq!(overage_due(), 0);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::domain::*;
+use crate::services::{with_state, with_state_mut, EconState, JournalService};
+use candid::Principal;
+use crate::infra::runtime::{time, caller};
+
+/// Payer disputes over settled receipts, resolved by admins or arbiters while the agent payout is held
+pub struct DisputeService;
+
+impl DisputeService {
+    const DEFAULT_DISPUTE_WINDOW: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours in nanoseconds
+    const MAX_DISPUTE_WINDOW: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+    const MAX_REASON_LENGTH: usize = 1_000;
+
+    pub fn get_dispute_window() -> u64 {
+        with_state(|state| state.dispute_window.unwrap_or(Self::DEFAULT_DISPUTE_WINDOW))
+    }
+
+    /// A zero window pays agents immediately and disables disputes for new settlements
+    pub fn set_dispute_window(window: u64) -> Result<(), String> {
+        if window > Self::MAX_DISPUTE_WINDOW {
+            return Err("Dispute window cannot exceed 30 days".to_string());
+        }
+
+        with_state_mut(|state| {
+            state.dispute_window = Some(window);
+            state.metrics.last_activity = time();
+        });
+
+        Ok(())
+    }
+
+    pub fn is_arbiter(principal_id: &str) -> bool {
+        with_state(|state| state.arbiters.as_ref().is_some_and(|arbiters| arbiters.iter().any(|p| p == principal_id)))
+    }
+
+    pub fn list_arbiters() -> Vec<String> {
+        with_state(|state| state.arbiters.clone().unwrap_or_default())
+    }
+
+    pub fn add_arbiter(principal: Principal) -> Result<(), String> {
+        if principal == Principal::anonymous() {
+            return Err("Arbiter cannot be anonymous".to_string());
+        }
+
+        let principal_id = principal.to_text();
+        with_state_mut(|state| {
+            let arbiters = state.arbiters.get_or_insert_with(Vec::new);
+            if !arbiters.contains(&principal_id) {
+                arbiters.push(principal_id);
+            }
+            state.metrics.last_activity = time();
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_arbiter(principal_id: &str) {
+        with_state_mut(|state| {
+            if let Some(arbiters) = state.arbiters.as_mut() {
+                arbiters.retain(|p| p != principal_id);
+            }
+            state.metrics.last_activity = time();
+        });
+    }
+
+    /// Payer disputes a settled receipt while its agent payout is still held
+    pub fn open_dispute(receipt_id: String, reason: String) -> Result<(), String> {
+        let now = time();
+        let caller_text = caller().to_text();
+
+        if reason.trim().is_empty() {
+            return Err("Dispute reason cannot be empty".to_string());
+        }
+        if reason.len() > Self::MAX_REASON_LENGTH {
+            return Err(format!("Dispute reason cannot exceed {} bytes", Self::MAX_REASON_LENGTH));
+        }
+
+        with_state_mut(|state| {
+            let receipt = state.receipts
+                .get(&receipt_id)
+                .ok_or_else(|| format!("Receipt not found: {}", receipt_id))?;
+
+            let payer = state.escrows
+                .get(&receipt.escrow_id)
+                .map(|escrow| escrow.principal_id.clone())
+                .ok_or_else(|| "Escrow not found".to_string())?;
+            if payer != caller_text {
+                return Err("Only the payer can dispute a receipt".to_string());
+            }
+
+            if receipt.dispute.is_some() {
+                return Err("Receipt has already been disputed".to_string());
+            }
+            if !matches!(receipt.settlement_status, SettlementStatus::Completed) {
+                return Err("Only completed settlements can be disputed".to_string());
+            }
+
+            let hold = receipt.payout_hold
+                .as_ref()
+                .ok_or_else(|| "Receipt was settled without a dispute window".to_string())?;
+            if hold.released_at.is_some() || hold.release_at <= now {
+                return Err("Dispute window has closed".to_string());
+            }
+
+            if let Some(receipt) = state.receipts.get_mut(&receipt_id) {
+                receipt.dispute = Some(Dispute {
+                    opened_by: caller_text.clone(),
+                    reason: reason.clone(),
+                    opened_at: now,
+                    resolution: None,
+                    resolved_by: None,
+                    resolved_at: None,
+                });
+            }
+            Self::transition(state, &receipt_id, SettlementStatus::Disputed, &caller_text, Some(reason), now);
+            state.metrics.last_activity = now;
+            Ok(())
+        })
+    }
+
+    /// Settle a dispute by paying the held payout out to the agent, the payer, or split between them
+    pub fn resolve_dispute(receipt_id: String, resolution: DisputeResolution) -> Result<(), String> {
+        let now = time();
+        let caller_text = caller().to_text();
+
+        with_state_mut(|state| {
+            let receipt = state.receipts
+                .get(&receipt_id)
+                .cloned()
+                .ok_or_else(|| format!("Receipt not found: {}", receipt_id))?;
+            if !matches!(receipt.settlement_status, SettlementStatus::Disputed) {
+                return Err("Receipt is not disputed".to_string());
+            }
+
+            let hold = receipt.payout_hold
+                .clone()
+                .ok_or_else(|| "Receipt has no held payout".to_string())?;
+            if hold.released_at.is_some() {
+                return Err("Held payout was already released".to_string());
+            }
+
+            let payer = state.escrows
+                .get(&receipt.escrow_id)
+                .map(|escrow| escrow.principal_id.clone())
+                .ok_or_else(|| "Escrow not found".to_string())?;
+
+            let refund = match &resolution {
+                DisputeResolution::FullPayout => 0,
+                DisputeResolution::PartialRefund { refund_amount } => {
+                    if *refund_amount == 0 || *refund_amount > hold.amount {
+                        return Err(format!("Partial refund must be between 1 and the held payout of {}", hold.amount));
+                    }
+                    *refund_amount
+                }
+                DisputeResolution::FullRefund => hold.amount,
+            };
+            let payout = hold.amount - refund;
+
+            // Multi-agent payouts shrink pro rata, so each agent bears its share of the refund
+            let distribution = hold.distribution();
+            let weights: Vec<u64> = distribution.iter().map(|(_, amount)| *amount).collect();
+            let paid: Vec<(String, u64)> = distribution
+                .iter()
+                .map(|(principal_id, _)| principal_id.clone())
+                .zip(Money::from_e8s(payout).split_by_weights(&weights)?.into_iter().map(Money::e8s))
+                .collect();
+
+            let mut postings = vec![Posting::debit(JournalAccount::PayoutHold(receipt_id.clone()), hold.amount)];
+            postings.extend(Self::payout_postings(&paid));
+            if refund > 0 {
+                postings.push(Posting::credit(JournalAccount::UserAvailable(payer.clone()), refund));
+            }
+            let kind = if refund > 0 { JournalEntryKind::DisputeRefund } else { JournalEntryKind::PayoutRelease };
+            JournalService::post(state, kind, &receipt_id, postings)?;
+
+            if let Some(receipt) = state.receipts.get_mut(&receipt_id) {
+                if let Some(hold) = receipt.payout_hold.as_mut() {
+                    hold.released_at = Some(now);
+                }
+                if let Some(dispute) = receipt.dispute.as_mut() {
+                    dispute.resolution = Some(resolution.clone());
+                    dispute.resolved_by = Some(caller_text.clone());
+                    dispute.resolved_at = Some(now);
+                }
+                if refund > 0 {
+                    let lines = receipt.lines.get_or_insert_with(Vec::new);
+                    lines.retain(|line| line.kind != ReceiptLineKind::AgentPayout);
+                    let payout_lines = paid
+                        .iter()
+                        .filter(|(_, amount)| *amount > 0)
+                        .map(|(principal_id, amount)| ReceiptLine {
+                            kind: ReceiptLineKind::AgentPayout,
+                            recipient: principal_id.clone(),
+                            amount: *amount,
+                        });
+                    lines.splice(0..0, payout_lines);
+                    lines.push(ReceiptLine {
+                        kind: ReceiptLineKind::Refund,
+                        recipient: payer,
+                        amount: refund,
+                    });
+                }
+            }
+
+            let status = if payout == 0 { SettlementStatus::Refunded } else { SettlementStatus::Completed };
+            Self::transition(state, &receipt_id, status, &caller_text, Some(format!("{:?}", resolution)), now);
+            state.metrics.last_activity = now;
+            Ok(())
+        })
+    }
+
+    /// Pay out held agent payouts whose dispute window closed without a dispute; returns how many were released
+    pub fn release_matured_payouts(limit: u32) -> u32 {
+        let now = time();
+
+        with_state_mut(|state| {
+            let matured: Vec<(String, PayoutHold)> = state.receipts
+                .values()
+                .filter(|receipt| matches!(receipt.settlement_status, SettlementStatus::Completed))
+                .filter_map(|receipt| {
+                    receipt.payout_hold
+                        .as_ref()
+                        .filter(|hold| hold.released_at.is_none() && hold.release_at <= now)
+                        .map(|hold| (receipt.receipt_id.clone(), hold.clone()))
+                })
+                .take(limit as usize)
+                .collect();
+
+            let mut released = 0;
+            for (receipt_id, hold) in matured {
+                let mut postings = vec![Posting::debit(JournalAccount::PayoutHold(receipt_id.clone()), hold.amount)];
+                postings.extend(Self::payout_postings(&hold.distribution()));
+                let result = JournalService::post(state, JournalEntryKind::PayoutRelease, &receipt_id, postings);
+                match result {
+                    Ok(_) => {
+                        if let Some(hold) = state.receipts.get_mut(&receipt_id).and_then(|r| r.payout_hold.as_mut()) {
+                            hold.released_at = Some(now);
+                        }
+                        released += 1;
+                    }
+                    Err(e) => log::error!("Failed to release payout for receipt {}: {}", receipt_id, e),
+                }
+            }
+
+            if released > 0 {
+                state.metrics.last_activity = now;
+            }
+            released
+        })
+    }
+
+    /// Disputed receipts; only those awaiting resolution when `open_only` is set
+    pub fn list_disputes(open_only: bool, limit: u32) -> Vec<Receipt> {
+        with_state(|state| {
+            state.receipts
+                .values()
+                .filter(|receipt| match receipt.dispute.as_ref() {
+                    Some(dispute) => !open_only || dispute.resolution.is_none(),
+                    None => false,
+                })
+                .take(limit as usize)
+                .cloned()
+                .collect()
+        })
+    }
+
+    fn payout_postings(distribution: &[(String, u64)]) -> Vec<Posting> {
+        distribution
+            .iter()
+            .filter(|(_, amount)| *amount > 0)
+            .map(|(principal_id, amount)| Posting::credit(JournalAccount::AgentEarnings(principal_id.clone()), *amount))
+            .collect()
+    }
+
+    // Record a status change on the receipt and keep its settlement entry in step
+    fn transition(
+        state: &mut EconState,
+        receipt_id: &str,
+        status: SettlementStatus,
+        changed_by: &str,
+        note: Option<String>,
+        now: u64,
+    ) {
+        if let Some(receipt) = state.receipts.get_mut(receipt_id) {
+            receipt.settlement_status = status.clone();
+            receipt.status_history.get_or_insert_with(Vec::new).push(SettlementTransition {
+                status: status.clone(),
+                changed_by: changed_by.to_string(),
+                changed_at: now,
+                note,
+            });
+        }
+
+        let settlement_id = state.receipt_to_settlement.get(receipt_id).cloned();
+        if let Some(entry) = settlement_id.and_then(|id| state.settlements.get_mut(&id)) {
+            entry.status = status;
+        }
+    }
+}
@@ -1,23 +1,91 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut, BalanceService};
-use ic_cdk::api::{time, caller};
+use crate::services::{with_state, with_state_mut, BalanceService, EstimationService, JournalService, RegistryService, TreasuryService};
+use crate::infra::{InFlightLock, LedgerClient};
+use crate::infra::runtime::{time, caller};
 use sha2::{Sha256, Digest};
 use base64::{Engine as _, engine::general_purpose};
+use std::cell::Cell;
+
+thread_local! {
+    // Disambiguates escrows opened in the same round; time() separates rounds and upgrades
+    static ESCROW_NONCE: Cell<u64> = const { Cell::new(0) };
+}
 
 pub struct EscrowService;
 
 impl EscrowService {
-    const ESCROW_TTL: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours in nanoseconds
+    const MAX_QUOTE_BUFFER_BPS: u32 = 5_000; // 50% on top of the quoted cost
+    
+    pub async fn create_escrow(
+        job_id: String,
+        amount: u64,
+        funding: EscrowFunding,
+        ttl: Option<u64>,
+    ) -> Result<String, String> {
+        Self::open_escrow(job_id, amount, funding, ttl, None).await
+    }
+    
+    /// Lock the quoted cost, plus an optional buffer, for the quote's job.
+    /// The quote must be unexpired, unused and issued to the caller.
+    pub async fn create_escrow_for_quote(
+        quote_id: String,
+        buffer_bps: Option<u32>,
+        funding: EscrowFunding,
+        ttl: Option<u64>,
+    ) -> Result<String, String> {
+        let caller_text = caller().to_text();
+        let _lock = InFlightLock::acquire(format!("quote:{}", quote_id))?;
+        
+        let stored = EstimationService::validate_quote(&quote_id)?;
+        if stored.principal_id != caller_text {
+            return Err("Quote was issued to another principal".to_string());
+        }
+        
+        let buffer_bps = buffer_bps.unwrap_or(0);
+        if buffer_bps > Self::MAX_QUOTE_BUFFER_BPS {
+            return Err(format!("Quote buffer cannot exceed {} bps", Self::MAX_QUOTE_BUFFER_BPS));
+        }
+        let multiplier = BasisPoints(BasisPoints::ONE_HUNDRED_PERCENT.value() + buffer_bps);
+        let amount = Money::from_e8s(stored.quote.estimated_cost).apply_bps_ceil(multiplier)?.e8s();
+        
+        let escrow_id = Self::open_escrow(stored.quote.job_id.clone(), amount, funding, ttl, Some(&stored)).await?;
+        
+        with_state_mut(|state| {
+            if let Some(quote) = state.quotes.as_mut().and_then(|quotes| quotes.get_mut(&quote_id)) {
+                quote.escrow_id = Some(escrow_id.clone());
+            }
+        });
+        
+        Ok(escrow_id)
+    }
     
-    pub async fn create_escrow(job_id: String, amount: u64) -> Result<String, String> {
+    async fn open_escrow(
+        job_id: String,
+        amount: u64,
+        funding: EscrowFunding,
+        ttl: Option<u64>,
+        quote: Option<&StoredQuote>,
+    ) -> Result<String, String> {
         let now = time();
-        let escrow_id = Self::generate_escrow_id(&job_id);
-        let principal_id = caller().to_text();
+        let ttl = Self::resolve_ttl(ttl)?;
+        let caller_principal = caller();
+        let principal_id = caller_principal.to_text();
+        let escrow_id = Self::generate_escrow_id(&principal_id, &job_id);
         
-        // Check if user has sufficient balance
-        let balance = BalanceService::get_balance(&principal_id)?;
-        if balance.available_balance < amount {
-            return Err("Insufficient balance".to_string());
+        // Pull exactly the escrowed amount from the caller's ICRC-2 allowance into their available
+        // balance. The reference is fixed per job, so a retry after an unknown outcome reuses the
+        // same pull instead of charging again.
+        if funding == EscrowFunding::Allowance {
+            let reference = format!("escrow-funding:{}:{}", principal_id, job_id);
+            BalanceService::pull(
+                &reference,
+                caller_principal,
+                LedgerClient::canister_account(None),
+                amount,
+                Some(JournalAccount::UserAvailable(principal_id.clone())),
+                JournalEntryKind::Deposit,
+            ).await?;
+            BalanceService::complete_pull(&reference);
         }
         
         let escrow = EscrowAccount {
@@ -27,19 +95,28 @@ impl EscrowService {
             amount,
             status: EscrowStatus::Active,
             created_at: now,
-            expires_at: now + Self::ESCROW_TTL,
+            expires_at: now.saturating_add(ttl),
+            paid_amount: Some(0),
+            refunded_amount: Some(0),
+            remaining_amount: Some(amount),
+            settlement_ids: Some(Vec::new()),
+            fee_policy_version: Some(match quote {
+                Some(stored) => stored.fee_policy_version,
+                None => with_state(|state| state.fee_policy_version()),
+            }),
+            quote_id: quote.map(|stored| stored.quote.quote_id.clone()),
+            settlers: None,
         };
         
         with_state_mut(|state| {
             // Move funds from available to escrowed
-            if let Some(user_balance) = state.balances.get_mut(&principal_id) {
-                user_balance.available_balance -= amount;
-                user_balance.escrowed_balance += amount;
-                user_balance.last_updated = now;
-            }
+            let mut postings = JournalService::spend_postings(state, &principal_id, amount)?;
+            postings.push(Posting::credit(JournalAccount::UserEscrow(principal_id.clone()), amount));
+            JournalService::post(state, JournalEntryKind::EscrowLock, &escrow_id, postings)?;
             
             state.escrows.insert(escrow_id.clone(), escrow);
-        });
+            Ok::<(), String>(())
+        })?;
         
         Ok(escrow_id)
     }
@@ -53,80 +130,172 @@ impl EscrowService {
         })
     }
     
-    pub fn release_escrow(escrow_id: String, recipient: String, amount: u64) -> Result<(), String> {
-        let now = time();
-        
+    /// Pay each `(account, amount)` in `payouts` and `protocol_fee` to the treasury/fee sinks out of the
+    /// escrow. When `close` is set, or the escrow is exhausted, the unused remainder is returned to
+    /// the holder. Returns the refunded amount.
+    pub fn release_escrow(
+        escrow_id: String,
+        settlement_id: &str,
+        payouts: Vec<(JournalAccount, u64)>,
+        protocol_fee: u64,
+        close: bool,
+    ) -> Result<u64, String> {
         with_state_mut(|state| {
+            let escrow = state.escrows
+                .get(&escrow_id)
+                .cloned()
+                .ok_or_else(|| "Escrow not found".to_string())?;
+            
+            if !matches!(escrow.status, EscrowStatus::Active) {
+                return Err("Escrow is not active".to_string());
+            }
+            
+            let payout = payouts
+                .iter()
+                .try_fold(Money::ZERO, |total, (_, amount)| total.checked_add(Money::from_e8s(*amount)))?
+                .e8s();
+            let amount = payout.checked_add(protocol_fee).ok_or_else(|| "Settlement amount overflow".to_string())?;
+            let remaining = escrow.remaining();
+            if remaining < amount {
+                return Err(format!("Settlement of {} would overdraw escrow (remaining {})", amount, remaining));
+            }
+            
+            let escrow_account = JournalAccount::UserEscrow(escrow.principal_id.clone());
+            // Return whatever the job did not use when closing
+            let refund = if close { remaining - amount } else { 0 };
+            
+            // Fee, payouts and refund leave the escrow as one balanced entry, so a failure moves nothing
+            let mut postings = vec![Posting::debit(escrow_account, amount + refund)];
+            // Protocol fee goes to the treasury and any configured fee sinks
+            postings.extend(TreasuryService::fee_postings(state, protocol_fee)?);
+            postings.extend(
+                payouts
+                    .into_iter()
+                    .filter(|(_, amount)| *amount > 0)
+                    .map(|(account, amount)| Posting::credit(account, amount)),
+            );
+            if refund > 0 {
+                postings.push(Posting::credit(JournalAccount::UserAvailable(escrow.principal_id.clone()), refund));
+            }
+            if amount + refund > 0 {
+                JournalService::post(state, JournalEntryKind::EscrowRelease, &escrow_id, postings)?;
+            }
+            
             if let Some(escrow) = state.escrows.get_mut(&escrow_id) {
-                if !matches!(escrow.status, EscrowStatus::Active) {
-                    return Err("Escrow is not active".to_string());
-                }
-                
-                if escrow.amount < amount {
-                    return Err("Insufficient escrow amount".to_string());
+                escrow.paid_amount = Some(escrow.total_paid() + amount);
+                escrow.refunded_amount = Some(escrow.total_refunded() + refund);
+                escrow.remaining_amount = Some(remaining - amount - refund);
+                escrow.settlement_ids.get_or_insert_with(Vec::new).push(settlement_id.to_string());
+                if remaining == amount + refund {
+                    escrow.status = EscrowStatus::Released;
                 }
-                
-                // Release funds to recipient
-                let recipient_balance = state.balances.entry(recipient.clone()).or_insert_with(|| Balance {
-                    principal_id: recipient,
-                    available_balance: 0,
-                    escrowed_balance: 0,
-                    total_earnings: 0,
-                    last_updated: now,
-                });
-                
-                recipient_balance.available_balance += amount;
-                recipient_balance.total_earnings += amount;
-                recipient_balance.last_updated = now;
-                
-                // Update escrow holder's balance
-                if let Some(holder_balance) = state.balances.get_mut(&escrow.principal_id) {
-                    holder_balance.escrowed_balance -= amount;
-                    holder_balance.last_updated = now;
-                }
-                
-                // Mark escrow as released
-                escrow.status = EscrowStatus::Released;
-                
-                Ok(())
-            } else {
-                Err("Escrow not found".to_string())
             }
+            
+            Ok(refund)
         })
     }
     
-    pub fn refund_escrow(escrow_id: String) -> Result<(), String> {
+    /// Push out an active escrow's expiry by `extension`; callable by the holder or an authorized
+    /// coordinator. The new expiry may be at most the policy's maximum TTL from now.
+    pub fn extend_escrow(escrow_id: String, extension: u64) -> Result<u64, String> {
         let now = time();
+        let caller_text = caller().to_text();
+        let max_ttl = with_state(|state| state.fee_policy().max_escrow_ttl());
+        
+        if extension == 0 {
+            return Err("Extension must be greater than zero".to_string());
+        }
+        
+        let escrow = Self::get_escrow(&escrow_id)?;
+        if escrow.principal_id != caller_text && !RegistryService::is_authorized_coordinator(&caller_text, &escrow) {
+            return Err("Only the escrow holder or an authorized coordinator can extend it".to_string());
+        }
+        if !matches!(escrow.status, EscrowStatus::Active) {
+            return Err("Escrow is not active".to_string());
+        }
+        if escrow.expires_at < now {
+            return Err("Escrow has already expired".to_string());
+        }
+        
+        let expires_at = escrow.expires_at.saturating_add(extension);
+        if expires_at - now > max_ttl {
+            return Err(format!("Escrow cannot be extended beyond the maximum TTL of {} ns from now", max_ttl));
+        }
         
         with_state_mut(|state| {
             if let Some(escrow) = state.escrows.get_mut(&escrow_id) {
-                if !matches!(escrow.status, EscrowStatus::Active) {
-                    return Err("Escrow is not active".to_string());
-                }
-                
-                // Refund to original holder
-                if let Some(holder_balance) = state.balances.get_mut(&escrow.principal_id) {
-                    holder_balance.available_balance += escrow.amount;
-                    holder_balance.escrowed_balance -= escrow.amount;
-                    holder_balance.last_updated = now;
-                }
-                
-                escrow.status = EscrowStatus::Refunded;
-                
-                Ok(())
-            } else {
-                Err("Escrow not found".to_string())
+                escrow.expires_at = expires_at;
             }
+            state.metrics.last_activity = now;
+        });
+        
+        Ok(expires_at)
+    }
+    
+    /// Requested TTL, or the policy default, checked against the policy maximum
+    fn resolve_ttl(ttl: Option<u64>) -> Result<u64, String> {
+        let policy = with_state(|state| state.fee_policy());
+        let ttl = ttl.unwrap_or_else(|| policy.default_escrow_ttl());
+        
+        if ttl == 0 {
+            return Err("Escrow TTL must be greater than zero".to_string());
+        }
+        if ttl > policy.max_escrow_ttl() {
+            return Err(format!("Escrow TTL cannot exceed {} ns", policy.max_escrow_ttl()));
+        }
+        
+        Ok(ttl)
+    }
+    
+    /// Close a partially settled escrow, returning the unused remainder to the holder
+    pub fn close_escrow(escrow_id: String) -> Result<u64, String> {
+        let caller_text = caller().to_text();
+        
+        with_state_mut(|state| {
+            let escrow = state.escrows
+                .get(&escrow_id)
+                .ok_or_else(|| "Escrow not found".to_string())?;
+            
+            if escrow.principal_id != caller_text && !state.admins.iter().any(|p| p == &caller_text) {
+                return Err("Only the escrow holder can close it".to_string());
+            }
+            
+            if !matches!(escrow.status, EscrowStatus::Active) {
+                return Err("Escrow is not active".to_string());
+            }
+            
+            let final_status = if escrow.total_paid() > 0 { EscrowStatus::Released } else { EscrowStatus::Refunded };
+            Self::refund_escrow_internal(escrow_id, state, final_status)
         })
     }
     
-    pub fn cleanup_expired_escrows() -> u32 {
-        let now = time();
+    /// Refund an active escrow in full; callable by the holder or an admin
+    pub fn refund_escrow(escrow_id: String) -> Result<(), String> {
+        let caller_text = caller().to_text();
         
         with_state_mut(|state| {
-            let mut expired_count = 0;
+            let escrow = state.escrows
+                .get(&escrow_id)
+                .ok_or_else(|| "Escrow not found".to_string())?;
+            
+            if escrow.principal_id != caller_text && !state.admins.iter().any(|p| p == &caller_text) {
+                return Err("Only the escrow holder can refund it".to_string());
+            }
             
-            let expired_ids: Vec<String> = state.escrows
+            if !matches!(escrow.status, EscrowStatus::Active) {
+                return Err("Escrow is not active".to_string());
+            }
+            
+            Self::refund_escrow_internal(escrow_id, state, EscrowStatus::Refunded).map(|_| ())
+        })
+    }
+    
+    /// Expire up to `limit` escrows past `expires_at`, returning their remaining funds to the holders
+    pub fn cleanup_expired_escrows(limit: u32) -> ExpiryBatch {
+        let now = time();
+        
+        with_state_mut(|state| {
+            let mut expired_ids: Vec<String> = state.escrows
                 .iter()
                 .filter(|(_, escrow)| {
                     matches!(escrow.status, EscrowStatus::Active) && escrow.expires_at < now
@@ -134,37 +303,183 @@ impl EscrowService {
                 .map(|(id, _)| id.clone())
                 .collect();
             
+            let backlog = expired_ids.len().saturating_sub(limit as usize) as u32;
+            expired_ids.truncate(limit as usize);
+            
+            let mut batch = ExpiryBatch { expired: 0, failed: 0, backlog };
             for escrow_id in expired_ids {
-                if Self::refund_escrow_internal(escrow_id, state).is_ok() {
-                    expired_count += 1;
+                match Self::refund_escrow_internal(escrow_id.clone(), state, EscrowStatus::Expired) {
+                    Ok(_) => batch.expired += 1,
+                    Err(e) => {
+                        log::error!("Failed to expire escrow {}: {}", escrow_id, e);
+                        batch.failed += 1;
+                    }
                 }
             }
             
-            expired_count
+            batch
         })
     }
     
-    fn refund_escrow_internal(escrow_id: String, state: &mut crate::services::EconState) -> Result<(), String> {
-        let now = time();
+    /// Return the escrow's remaining funds to the holder and move it to `final_status`
+    fn refund_escrow_internal(
+        escrow_id: String,
+        state: &mut crate::services::EconState,
+        final_status: EscrowStatus,
+    ) -> Result<u64, String> {
+        let (holder, amount) = match state.escrows.get(&escrow_id) {
+            Some(escrow) => (escrow.principal_id.clone(), escrow.remaining()),
+            None => return Err("Escrow not found".to_string()),
+        };
+        
+        let kind = if matches!(final_status, EscrowStatus::Expired) {
+            JournalEntryKind::EscrowExpiry
+        } else {
+            JournalEntryKind::EscrowRefund
+        };
+        
+        // Return the locked funds to the original holder
+        if amount > 0 {
+            JournalService::transfer(
+                state,
+                kind,
+                &escrow_id,
+                JournalAccount::UserEscrow(holder.clone()),
+                JournalAccount::UserAvailable(holder),
+                amount,
+            )?;
+        }
         
         if let Some(escrow) = state.escrows.get_mut(&escrow_id) {
-            if let Some(holder_balance) = state.balances.get_mut(&escrow.principal_id) {
-                holder_balance.available_balance += escrow.amount;
-                holder_balance.escrowed_balance -= escrow.amount;
-                holder_balance.last_updated = now;
-            }
-            
-            escrow.status = EscrowStatus::Expired;
+            escrow.status = final_status;
+            escrow.refunded_amount = Some(escrow.total_refunded() + amount);
+            escrow.remaining_amount = Some(0);
         }
         
-        Ok(())
+        Ok(amount)
     }
     
-    fn generate_escrow_id(job_id: &str) -> String {
+    fn generate_escrow_id(principal_id: &str, job_id: &str) -> String {
+        let nonce = ESCROW_NONCE.with(|counter| {
+            let nonce = counter.get();
+            counter.set(nonce.wrapping_add(1));
+            nonce
+        });
         let mut hasher = Sha256::new();
+        hasher.update(principal_id.as_bytes());
         hasher.update(job_id.as_bytes());
         hasher.update(time().to_be_bytes());
+        hasher.update(nonce.to_be_bytes());
         let hash = hasher.finalize();
         format!("escrow_{}", general_purpose::STANDARD.encode(&hash[..8]))
     }
-}
\ No newline at end of file
+}
+
+/// Outcome of one bounded expiry pass
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExpiryBatch {
+    pub expired: u32,
+    pub failed: u32,
+    // Expired escrows left for a later pass
+    pub backlog: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::EconState;
+
+    fn open(state: &mut EconState, amount: u64) {
+        JournalService::transfer(state, JournalEntryKind::EscrowLock, "escrow_1", JournalAccount::External, JournalAccount::UserEscrow("alice".to_string()), amount).unwrap();
+        state.escrows.insert("escrow_1".to_string(), EscrowAccount {
+            escrow_id: "escrow_1".to_string(),
+            job_id: "job_1".to_string(),
+            principal_id: "alice".to_string(),
+            amount,
+            status: EscrowStatus::Active,
+            created_at: 0,
+            expires_at: u64::MAX,
+            paid_amount: Some(0),
+            refunded_amount: Some(0),
+            remaining_amount: Some(amount),
+            settlement_ids: Some(Vec::new()),
+            fee_policy_version: None,
+            quote_id: None,
+            settlers: None,
+        });
+    }
+
+    fn journal_entries() -> usize {
+        with_state(|state| state.journal.as_ref().map_or(0, |journal| journal.entries.len()))
+    }
+
+    #[test]
+    fn release_posts_fee_payout_and_refund_as_one_entry() {
+        with_state_mut(|state| open(state, 1_000));
+        let agent = JournalAccount::AgentEarnings("agent".to_string());
+
+        let refunded = EscrowService::release_escrow("escrow_1".to_string(), "settlement_1", vec![(agent.clone(), 600)], 50, true).unwrap();
+
+        assert_eq!(refunded, 350);
+        assert_eq!(journal_entries(), 2);
+        assert_eq!(JournalService::account_balance(&agent), 600);
+        assert_eq!(JournalService::account_balance(&JournalAccount::ProtocolTreasury), 50);
+        assert_eq!(JournalService::account_balance(&JournalAccount::UserAvailable("alice".to_string())), 350);
+        assert_eq!(JournalService::account_balance(&JournalAccount::UserEscrow("alice".to_string())), 0);
+
+        let escrow = EscrowService::get_escrow("escrow_1").unwrap();
+        assert!(matches!(escrow.status, EscrowStatus::Released));
+        assert_eq!((escrow.total_paid(), escrow.total_refunded(), escrow.remaining()), (650, 350, 0));
+    }
+
+    #[test]
+    fn failed_release_moves_nothing() {
+        with_state_mut(|state| {
+            open(state, 1_000);
+            // A fee sink share that cannot be applied fails the fee leg
+            state.fee_sinks = Some(vec![FeeSink { name: "ops".to_string(), share_bps: u32::MAX }]);
+        });
+        let agent = JournalAccount::AgentEarnings("agent".to_string());
+
+        assert!(EscrowService::release_escrow("escrow_1".to_string(), "settlement_1", vec![(agent.clone(), 600)], 50, false).is_err());
+
+        assert_eq!(journal_entries(), 1);
+        assert_eq!(JournalService::account_balance(&agent), 0);
+        assert_eq!(EscrowService::get_escrow("escrow_1").unwrap().remaining(), 1_000);
+    }
+
+    #[test]
+    fn allowance_funding_with_lost_reply_charges_once() {
+        use crate::infra::ledger::{Account, ApproveArgs};
+        use crate::infra::mock_ledger::{MockFault, MockLedger};
+        use crate::infra::runtime::{self, block_on};
+        use candid::{Nat, Principal};
+
+        MockLedger::reset();
+        runtime::set_time(1_000_000_000);
+        let alice = Principal::from_slice(&[1; 10]);
+        let wallet = Account { owner: alice, subaccount: None };
+        runtime::set_caller(alice);
+        MockLedger::mint(wallet.clone(), 1_010_000);
+        MockLedger::icrc2_approve(alice, ApproveArgs {
+            from_subaccount: None,
+            spender: LedgerClient::canister_account(None),
+            amount: Nat::from(1_000_000u64),
+            expected_allowance: None,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }).unwrap();
+
+        MockLedger::inject_fault(MockFault::LoseReply);
+        assert!(block_on(EscrowService::create_escrow("job_1".to_string(), 400_000, EscrowFunding::Allowance, None)).is_err());
+        runtime::advance_time(1);
+        let escrow_id = block_on(EscrowService::create_escrow("job_1".to_string(), 400_000, EscrowFunding::Allowance, None)).unwrap();
+
+        assert_eq!(MockLedger::icrc1_balance_of(wallet), Nat::from(1_000_000u64 - 400_000 - 10_000));
+        assert_eq!(EscrowService::get_escrow(&escrow_id).unwrap().remaining(), 400_000);
+        assert_eq!(JournalService::account_balance(&JournalAccount::UserAvailable(alice.to_text())), 0);
+        assert!(JournalService::reconcile().conserved);
+    }
+}
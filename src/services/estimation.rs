@@ -1,46 +1,80 @@
 use crate::domain::*;
 use crate::services::{with_state, with_state_mut};
-use ic_cdk::api::time;
+use crate::infra::runtime::{time, caller};
 use sha2::{Sha256, Digest};
 use base64::{Engine as _, engine::general_purpose};
+use std::cell::Cell;
+use std::collections::HashMap;
+
+thread_local! {
+    // Disambiguates quotes issued in the same round; time() separates rounds and upgrades
+    static QUOTE_NONCE: Cell<u64> = const { Cell::new(0) };
+}
 
 pub struct EstimationService;
 
 impl EstimationService {
     const BASE_COST_PER_TOKEN: u64 = 100; // 0.0001 tokens per output token
     const COMPUTE_CYCLE_COST: u64 = 10;   // 0.00001 tokens per compute cycle
+    const QUOTE_TTL: u64 = 15 * 60 * 1_000_000_000; // 15 minutes
+    const MAX_OPEN_QUOTES_PER_CALLER: usize = 100;
     
     pub fn estimate_cost(job_spec: JobSpec) -> Result<CostQuote, String> {
         let now = time();
         
         with_state_mut(|state| {
+            let fee_policy = state.fee_policy();
+
             // Calculate base cost
-            let token_cost = job_spec.estimated_tokens as u64 * Self::BASE_COST_PER_TOKEN;
-            let compute_cost = job_spec.estimated_compute_cycles * Self::COMPUTE_CYCLE_COST;
-            let base_cost = token_cost + compute_cost;
+            let token_cost = Money::from_e8s(Self::BASE_COST_PER_TOKEN).checked_mul(job_spec.estimated_tokens as u64)?;
+            let compute_cost = Money::from_e8s(Self::COMPUTE_CYCLE_COST).checked_mul(job_spec.estimated_compute_cycles)?;
+            let base_cost = token_cost.checked_add(compute_cost)?;
             
-            // Apply priority multiplier
-            let priority_multiplier = Self::get_priority_multiplier(&job_spec.priority, &state.fee_policy);
-            let adjusted_cost = (base_cost as f64 * priority_multiplier as f64) as u64;
+            // Apply priority multiplier; a price, so it rounds up
+            let priority_multiplier = fee_policy.priority_multiplier(&job_spec.priority);
+            let adjusted_cost = base_cost.apply_bps_ceil(priority_multiplier)?;
             
-            // Calculate protocol fee
-            let protocol_fee = ((adjusted_cost as f64) * (state.fee_policy.protocol_fee_percentage as f64 / 100.0)) as u64;
-            let total_cost = adjusted_cost + protocol_fee;
+            // Calculate protocol and agent fees; fees, so they round down. Settlement charges both
+            // on top of the base, so the quote must cover them for its escrow to settle.
+            let protocol_fee = adjusted_cost.apply_bps(fee_policy.protocol_fee_rate())?;
+            let agent_fee = adjusted_cost.apply_bps(fee_policy.agent_fee_rate())?;
+            let total_cost = adjusted_cost.checked_add(protocol_fee)?.checked_add(agent_fee)?;
             
             // Ensure minimum fee
-            let final_cost = total_cost.max(state.fee_policy.minimum_fee);
+            let final_cost = total_cost.max(Money::from_e8s(fee_policy.minimum_fee));
             
-            let quote_id = Self::generate_quote_id(&job_spec.job_id);
+            let principal_id = caller().to_text();
+            let quote_id = Self::generate_quote_id(&principal_id, &job_spec.job_id);
             let quote = CostQuote {
                 job_id: job_spec.job_id,
-                estimated_cost: final_cost,
-                base_cost,
-                priority_multiplier,
-                protocol_fee,
-                quote_expires_at: now + 15 * 60 * 1_000_000_000, // 15 minutes
-                quote_id,
+                estimated_cost: final_cost.e8s(),
+                base_cost: base_cost.e8s(),
+                priority_multiplier_bps: priority_multiplier.value(),
+                protocol_fee: protocol_fee.e8s(),
+                quote_expires_at: now + Self::QUOTE_TTL,
+                quote_id: quote_id.clone(),
             };
             
+            // Keep the quote so an escrow can be bound to it; drop expired quotes nobody used
+            let fee_policy_version = state.fee_policy_version();
+            let quotes = state.quotes.get_or_insert_with(HashMap::new);
+            quotes.retain(|_, stored| stored.escrow_id.is_some() || stored.quote.quote_expires_at >= now);
+            let open = quotes.values()
+                .filter(|stored| stored.escrow_id.is_none() && stored.principal_id == principal_id)
+                .count();
+            if open >= Self::MAX_OPEN_QUOTES_PER_CALLER {
+                return Err(format!("At most {} unused quotes may be open at a time", Self::MAX_OPEN_QUOTES_PER_CALLER));
+            }
+            if quotes.contains_key(&quote_id) {
+                return Err("Quote id collision; retry the estimate".to_string());
+            }
+            quotes.insert(quote_id, StoredQuote {
+                quote: quote.clone(),
+                principal_id,
+                fee_policy_version,
+                escrow_id: None,
+            });
+            
             state.metrics.total_estimates += 1;
             state.metrics.last_activity = now;
             
@@ -48,18 +82,34 @@ impl EstimationService {
         })
     }
     
-    pub fn validate_quote(quote: &CostQuote) -> Result<(), String> {
+    pub fn get_quote(quote_id: &str) -> Result<StoredQuote, String> {
+        with_state(|state| {
+            state.quotes
+                .as_ref()
+                .and_then(|quotes| quotes.get(quote_id))
+                .cloned()
+                .ok_or_else(|| format!("Quote not found: {}", quote_id))
+        })
+    }
+    
+    /// Check that a stored quote can still back an escrow
+    pub fn validate_quote(quote_id: &str) -> Result<StoredQuote, String> {
         let now = time();
+        let stored = Self::get_quote(quote_id)?;
+        
+        if let Some(escrow_id) = stored.escrow_id.as_ref() {
+            return Err(format!("Quote already used for escrow {}", escrow_id));
+        }
         
-        if quote.quote_expires_at < now {
+        if stored.quote.quote_expires_at < now {
             return Err("Quote has expired".to_string());
         }
         
-        if quote.estimated_cost < quote.base_cost {
+        if stored.quote.estimated_cost < stored.quote.base_cost {
             return Err("Invalid quote: estimated cost less than base cost".to_string());
         }
         
-        Ok(())
+        Ok(stored)
     }
     
     pub fn estimate_variance(actual_cost: u64, estimated_cost: u64) -> f32 {
@@ -74,7 +124,7 @@ impl EstimationService {
     pub fn update_estimation_model(actual_costs: &[(JobSpec, u64)]) -> Result<(), String> {
         // Mock implementation for estimation model updates
         // In real implementation, this would use machine learning to improve estimates
-        with_state_mut(|state| {
+        with_state_mut(|_state| {
             let total_jobs = actual_costs.len();
             if total_jobs > 0 {
                 let average_variance = actual_costs
@@ -93,25 +143,64 @@ impl EstimationService {
         })
     }
     
-    fn get_priority_multiplier(priority: &JobPriority, policy: &FeePolicy) -> f32 {
-        let priority_key = match priority {
-            JobPriority::Low => "Low",
-            JobPriority::Normal => "Normal", 
-            JobPriority::High => "High",
-            JobPriority::Critical => "Critical",
-        };
-        
-        policy.priority_multipliers
-            .get(priority_key)
-            .copied()
-            .unwrap_or(1.0)
-    }
-    
-    fn generate_quote_id(job_id: &str) -> String {
+    fn generate_quote_id(principal_id: &str, job_id: &str) -> String {
+        let nonce = QUOTE_NONCE.with(|counter| {
+            let nonce = counter.get();
+            counter.set(nonce.wrapping_add(1));
+            nonce
+        });
         let mut hasher = Sha256::new();
+        hasher.update(principal_id.as_bytes());
         hasher.update(job_id.as_bytes());
         hasher.update(time().to_be_bytes());
+        hasher.update(nonce.to_be_bytes());
         let hash = hasher.finalize();
         format!("quote_{}", general_purpose::STANDARD.encode(&hash[..8]))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::runtime;
+    use candid::Principal;
+
+    fn job(job_id: &str) -> JobSpec {
+        JobSpec {
+            job_id: job_id.to_string(),
+            model_id: "model".to_string(),
+            estimated_tokens: 1_000,
+            estimated_compute_cycles: 0,
+            priority: JobPriority::Normal,
+        }
+    }
+
+    #[test]
+    fn quotes_in_one_round_get_distinct_ids() {
+        runtime::set_time(1_000_000_000);
+        runtime::set_caller(Principal::from_slice(&[1; 10]));
+        let first = EstimationService::estimate_cost(job("job_1")).unwrap();
+        let second = EstimationService::estimate_cost(job("job_1")).unwrap();
+        assert_ne!(first.quote_id, second.quote_id);
+        assert!(EstimationService::get_quote(&first.quote_id).is_ok());
+
+        // Another caller's quote for the same job in the same round does not collide either
+        runtime::set_caller(Principal::from_slice(&[2; 10]));
+        let other = EstimationService::estimate_cost(job("job_1")).unwrap();
+        assert_eq!(EstimationService::get_quote(&other.quote_id).unwrap().principal_id, Principal::from_slice(&[2; 10]).to_text());
+        assert_eq!(EstimationService::get_quote(&first.quote_id).unwrap().principal_id, Principal::from_slice(&[1; 10]).to_text());
+    }
+
+    #[test]
+    fn open_quotes_per_caller_are_capped() {
+        runtime::set_time(1_000_000_000);
+        runtime::set_caller(Principal::from_slice(&[1; 10]));
+        for _ in 0..EstimationService::MAX_OPEN_QUOTES_PER_CALLER {
+            EstimationService::estimate_cost(job("job_1")).unwrap();
+        }
+        assert!(EstimationService::estimate_cost(job("job_1")).is_err());
+
+        // Expired quotes stop counting
+        runtime::advance_time(EstimationService::QUOTE_TTL + 1);
+        assert!(EstimationService::estimate_cost(job("job_1")).is_ok());
+    }
+}
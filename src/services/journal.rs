@@ -0,0 +1,376 @@
+use crate::domain::*;
+use crate::services::{with_state, EconState};
+use crate::infra::runtime::time;
+use serde::{Deserialize, Serialize};
+use candid::CandidType;
+use std::collections::HashMap;
+
+/// Append-only double-entry journal; `EconState.balances` is a projection of it
+#[derive(Debug, Default, Clone, Serialize, Deserialize, CandidType)]
+pub struct Journal {
+    pub entries: Vec<JournalEntry>,
+    pub account_balances: HashMap<JournalAccount, u64>,
+    pub next_entry_id: u64,
+}
+
+impl Journal {
+    pub fn balance_of(&self, account: &JournalAccount) -> u64 {
+        self.account_balances.get(account).copied().unwrap_or(0)
+    }
+}
+
+/// Journal service: the only place allowed to move funds between accounts
+pub struct JournalService;
+
+impl JournalService {
+    /// Move `amount` from one account to another as a single balanced entry
+    pub fn transfer(
+        state: &mut EconState,
+        kind: JournalEntryKind,
+        reference: &str,
+        from: JournalAccount,
+        to: JournalAccount,
+        amount: u64,
+    ) -> Result<u64, String> {
+        Self::post(state, kind, reference, vec![Posting::debit(from, amount), Posting::credit(to, amount)])
+    }
+
+    /// Validate and apply a set of postings atomically, then refresh the affected balances
+    pub fn post(
+        state: &mut EconState,
+        kind: JournalEntryKind,
+        reference: &str,
+        postings: Vec<Posting>,
+    ) -> Result<u64, String> {
+        if postings.is_empty() {
+            return Err("Journal entry has no postings".to_string());
+        }
+
+        if postings.iter().any(|posting| posting.amount == 0) {
+            return Err("Journal postings must be greater than zero".to_string());
+        }
+
+        let (debits, credits) = Self::sum_sides(&postings);
+        if debits != credits {
+            return Err(format!("Unbalanced journal entry: debits {} != credits {}", debits, credits));
+        }
+
+        let now = time();
+        let journal = state.journal.get_or_insert_with(Journal::default);
+
+        // Compute every new account balance first so a failing posting leaves the journal untouched
+        let mut updated: HashMap<JournalAccount, u64> = HashMap::new();
+        for posting in &postings {
+            let current = updated
+                .get(&posting.account)
+                .copied()
+                .unwrap_or_else(|| journal.balance_of(&posting.account));
+            let increases = (posting.side == PostingSide::Debit) == posting.account.is_debit_normal();
+            let next = if increases {
+                current.checked_add(posting.amount)
+                    .ok_or_else(|| format!("Balance overflow in {:?}", posting.account))?
+            } else {
+                current.checked_sub(posting.amount)
+                    .ok_or_else(|| format!("Insufficient funds in {:?}", posting.account))?
+            };
+            updated.insert(posting.account.clone(), next);
+        }
+
+        journal.account_balances.extend(updated);
+
+        let entry_id = journal.next_entry_id;
+        journal.next_entry_id += 1;
+        journal.entries.push(JournalEntry {
+            entry_id,
+            kind,
+            reference: reference.to_string(),
+            postings: postings.clone(),
+            created_at: now,
+        });
+
+        Self::project_balances(state, &postings, now);
+
+        Ok(entry_id)
+    }
+
+    /// Debit postings drawing `amount` from a principal's spendable funds, available balance first then earnings
+    pub fn spend_postings(state: &EconState, principal_id: &str, amount: u64) -> Result<Vec<Posting>, String> {
+        let available_account = JournalAccount::UserAvailable(principal_id.to_string());
+        let earnings_account = JournalAccount::AgentEarnings(principal_id.to_string());
+        let available = Self::balance_in(state, &available_account);
+        let earnings = Self::balance_in(state, &earnings_account);
+
+        if available.saturating_add(earnings) < amount {
+            return Err("Insufficient balance".to_string());
+        }
+
+        let from_available = amount.min(available);
+        let from_earnings = amount - from_available;
+
+        let mut postings = Vec::new();
+        if from_available > 0 {
+            postings.push(Posting::debit(available_account, from_available));
+        }
+        if from_earnings > 0 {
+            postings.push(Posting::debit(earnings_account, from_earnings));
+        }
+        Ok(postings)
+    }
+
+    pub fn account_balance(account: &JournalAccount) -> u64 {
+        with_state(|state| Self::balance_in(state, account))
+    }
+
+    /// Journal entries touching any account owned by `principal_id`, newest first
+    pub fn entries_for_principal(principal_id: &str, limit: u32) -> Vec<JournalEntry> {
+        with_state(|state| {
+            state.journal.as_ref()
+                .map(|journal| {
+                    journal.entries
+                        .iter()
+                        .rev()
+                        .filter(|entry| {
+                            entry.postings.iter().any(|posting| posting.account.principal() == Some(principal_id))
+                        })
+                        .take(limit as usize)
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+    }
+
+    /// Replay the journal and compare it against the stored balances
+    pub fn reconcile() -> JournalReconciliation {
+        with_state(|state| {
+            let empty = Journal::default();
+            let journal = state.journal.as_ref().unwrap_or(&empty);
+
+            let mut total_assets: u64 = 0;
+            let mut total_liabilities: u64 = 0;
+            for (account, amount) in &journal.account_balances {
+                if account.is_debit_normal() {
+                    total_assets = total_assets.saturating_add(*amount);
+                } else {
+                    total_liabilities = total_liabilities.saturating_add(*amount);
+                }
+            }
+
+            let all_entries_balanced = journal.entries.iter().all(|entry| {
+                let (debits, credits) = Self::sum_sides(&entry.postings);
+                debits == credits
+            });
+
+            let mut earnings: HashMap<String, u64> = HashMap::new();
+            for entry in &journal.entries {
+                for posting in &entry.postings {
+                    if let (JournalAccount::AgentEarnings(p), PostingSide::Credit) = (&posting.account, &posting.side) {
+                        *earnings.entry(p.clone()).or_insert(0) += posting.amount;
+                    }
+                }
+            }
+
+            let mut discrepancies = Vec::new();
+            for (principal_id, recorded) in &state.balances {
+                let mut derived = Self::derive_balance(journal, principal_id, recorded.last_updated);
+                derived.total_earnings = earnings.get(principal_id).copied().unwrap_or(0);
+
+                // total_earnings is a lifetime statistic that may predate the journal, so only funds are compared
+                if derived.available_balance != recorded.available_balance
+                    || derived.escrowed_balance != recorded.escrowed_balance
+                {
+                    discrepancies.push(BalanceDiscrepancy {
+                        principal_id: principal_id.clone(),
+                        recorded: recorded.clone(),
+                        derived,
+                    });
+                }
+            }
+
+            JournalReconciliation {
+                conserved: all_entries_balanced && total_assets == total_liabilities,
+                total_assets,
+                total_liabilities,
+                entries_checked: journal.entries.len() as u64,
+                discrepancies,
+            }
+        })
+    }
+
+    /// Seed the journal from balances that predate it (used on upgrade)
+    pub fn open_from_balances(state: &mut EconState) -> Result<u32, String> {
+        let opening: Vec<(String, u64, u64)> = state.balances
+            .values()
+            .map(|balance| (balance.principal_id.clone(), balance.available_balance, balance.escrowed_balance))
+            .collect();
+
+        let mut opened = 0;
+        for (principal_id, available, escrowed) in opening {
+            let mut postings = Vec::new();
+            if available > 0 {
+                postings.push(Posting::credit(JournalAccount::UserAvailable(principal_id.clone()), available));
+            }
+            if escrowed > 0 {
+                postings.push(Posting::credit(JournalAccount::UserEscrow(principal_id.clone()), escrowed));
+            }
+            if postings.is_empty() {
+                continue;
+            }
+            postings.push(Posting::debit(JournalAccount::External, available + escrowed));
+
+            Self::post(state, JournalEntryKind::Opening, &principal_id, postings)?;
+            opened += 1;
+        }
+
+        Ok(opened)
+    }
+
+    fn balance_in(state: &EconState, account: &JournalAccount) -> u64 {
+        state.journal.as_ref().map(|journal| journal.balance_of(account)).unwrap_or(0)
+    }
+
+    fn derive_balance(journal: &Journal, principal_id: &str, now: u64) -> Balance {
+        let available = journal.balance_of(&JournalAccount::UserAvailable(principal_id.to_string()));
+        let earnings = journal.balance_of(&JournalAccount::AgentEarnings(principal_id.to_string()));
+        let escrowed = journal.balance_of(&JournalAccount::UserEscrow(principal_id.to_string()));
+
+        let mut balance = Balance::empty(principal_id, now);
+        balance.available_balance = available.saturating_add(earnings);
+        balance.escrowed_balance = escrowed;
+        balance
+    }
+
+    fn project_balances(state: &mut EconState, postings: &[Posting], now: u64) {
+        let mut earned: HashMap<String, u64> = HashMap::new();
+        let mut touched: Vec<String> = Vec::new();
+        for posting in postings {
+            if let Some(principal_id) = posting.account.principal() {
+                if !touched.iter().any(|p| p == principal_id) {
+                    touched.push(principal_id.to_string());
+                }
+                if let (JournalAccount::AgentEarnings(_), PostingSide::Credit) = (&posting.account, &posting.side) {
+                    *earned.entry(principal_id.to_string()).or_insert(0) += posting.amount;
+                }
+            }
+        }
+
+        let journal = match state.journal.as_ref() {
+            Some(journal) => journal,
+            None => return,
+        };
+
+        for principal_id in touched {
+            let derived = Self::derive_balance(journal, &principal_id, now);
+            let balance = state.balances
+                .entry(principal_id.clone())
+                .or_insert_with(|| Balance::empty(&principal_id, now));
+            balance.available_balance = derived.available_balance;
+            balance.escrowed_balance = derived.escrowed_balance;
+            balance.total_earnings += earned.get(&principal_id).copied().unwrap_or(0);
+            balance.last_updated = now;
+        }
+    }
+
+    fn sum_sides(postings: &[Posting]) -> (u128, u128) {
+        postings.iter().fold((0u128, 0u128), |(debits, credits), posting| match posting.side {
+            PostingSide::Debit => (debits + posting.amount as u128, credits),
+            PostingSide::Credit => (debits, credits + posting.amount as u128),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn available(principal_id: &str) -> JournalAccount {
+        JournalAccount::UserAvailable(principal_id.to_string())
+    }
+
+    fn funded(principal_id: &str, amount: u64) -> EconState {
+        let mut state = EconState::default();
+        JournalService::transfer(&mut state, JournalEntryKind::Deposit, "seed", JournalAccount::External, available(principal_id), amount).unwrap();
+        state
+    }
+
+    #[test]
+    fn transfer_updates_accounts_and_projected_balance() {
+        let state = funded("alice", 500);
+        let journal = state.journal.as_ref().unwrap();
+
+        assert_eq!(journal.balance_of(&JournalAccount::External), 500);
+        assert_eq!(journal.balance_of(&available("alice")), 500);
+        assert_eq!(journal.entries.len(), 1);
+        assert_eq!(state.balances["alice"].available_balance, 500);
+    }
+
+    #[test]
+    fn rejects_empty_unbalanced_and_zero_postings() {
+        let mut state = funded("alice", 500);
+
+        assert!(JournalService::post(&mut state, JournalEntryKind::Deposit, "empty", vec![]).is_err());
+
+        let unbalanced = vec![Posting::debit(JournalAccount::External, 100), Posting::credit(available("alice"), 90)];
+        let error = JournalService::post(&mut state, JournalEntryKind::Deposit, "unbalanced", unbalanced).unwrap_err();
+        assert!(error.starts_with("Unbalanced journal entry"), "{}", error);
+
+        let zero = vec![Posting::debit(JournalAccount::External, 0), Posting::credit(available("alice"), 0)];
+        assert!(JournalService::post(&mut state, JournalEntryKind::Deposit, "zero", zero).is_err());
+
+        assert_eq!(state.journal.as_ref().unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn overdraft_leaves_journal_untouched() {
+        let mut state = funded("alice", 500);
+
+        // The first posting would apply cleanly; the second overdraws
+        let postings = vec![
+            Posting::debit(available("alice"), 300),
+            Posting::debit(available("bob"), 200),
+            Posting::credit(JournalAccount::ProtocolTreasury, 500),
+        ];
+        let error = JournalService::post(&mut state, JournalEntryKind::EscrowLock, "overdraft", postings).unwrap_err();
+        assert!(error.starts_with("Insufficient funds"), "{}", error);
+
+        let journal = state.journal.as_ref().unwrap();
+        assert_eq!(journal.balance_of(&available("alice")), 500);
+        assert_eq!(journal.balance_of(&JournalAccount::ProtocolTreasury), 0);
+        assert_eq!(journal.entries.len(), 1);
+        assert_eq!(state.balances["alice"].available_balance, 500);
+    }
+
+    #[test]
+    fn spend_postings_draw_available_before_earnings() {
+        let mut state = funded("alice", 100);
+        JournalService::transfer(&mut state, JournalEntryKind::Deposit, "earned", JournalAccount::External, JournalAccount::AgentEarnings("alice".to_string()), 50).unwrap();
+
+        let postings: Vec<(JournalAccount, u64)> = JournalService::spend_postings(&state, "alice", 120)
+            .unwrap()
+            .into_iter()
+            .map(|posting| (posting.account, posting.amount))
+            .collect();
+        assert_eq!(postings, vec![(available("alice"), 100), (JournalAccount::AgentEarnings("alice".to_string()), 20)]);
+        assert!(JournalService::spend_postings(&state, "alice", 151).is_err());
+    }
+
+    #[test]
+    fn open_from_balances_seeds_legacy_balances() {
+        let mut state = EconState::default();
+        let mut alice = Balance::empty("alice", 0);
+        alice.available_balance = 300;
+        alice.escrowed_balance = 200;
+        state.balances.insert("alice".to_string(), alice);
+        state.balances.insert("bob".to_string(), Balance::empty("bob", 0));
+
+        assert_eq!(JournalService::open_from_balances(&mut state), Ok(1));
+
+        let journal = state.journal.as_ref().unwrap();
+        assert_eq!(journal.balance_of(&JournalAccount::External), 500);
+        assert_eq!(journal.balance_of(&available("alice")), 300);
+        assert_eq!(journal.balance_of(&JournalAccount::UserEscrow("alice".to_string())), 200);
+        assert_eq!(journal.entries[0].kind, JournalEntryKind::Opening);
+        assert_eq!(state.balances["alice"].available_balance, 300);
+        assert_eq!(state.balances["alice"].escrowed_balance, 200);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::domain::*;
-use ic_cdk::api::time;
+use crate::infra::runtime::time;
 use serde::{Deserialize, Serialize};
 use candid::CandidType;
 use std::collections::HashMap;
@@ -11,6 +11,12 @@ pub mod settlement;
 pub mod balance;
 pub mod subscription;
 pub mod payment;
+pub mod journal;
+pub mod treasury;
+pub mod registry;
+pub mod dispute;
+pub mod sweeper;
+pub mod billing;
 
 pub use estimation::EstimationService;
 pub use escrow::EscrowService;
@@ -18,6 +24,12 @@ pub use settlement::SettlementService;
 pub use balance::BalanceService;
 pub use subscription::SubscriptionService;
 pub use payment::PaymentService;
+pub use journal::JournalService;
+pub use treasury::TreasuryService;
+pub use registry::RegistryService;
+pub use dispute::DisputeService;
+pub use sweeper::SweeperService;
+pub use billing::BillingService;
 
 thread_local! {
     static STATE: RefCell<EconState> = RefCell::new(EconState::default());
@@ -31,7 +43,25 @@ pub struct EconState {
     pub settlements: HashMap<String, SettlementEntry>,
     // Map receipt_id -> settlement_id for O(1) integrity checks
     pub receipt_to_settlement: HashMap<String, String>,
-    pub fee_policy: FeePolicy,
+    // Optional so state saved with the old percentage-based policy decodes as None and is migrated
+    pub fee_policy: Option<FeePolicy>,
+    // Version of the current fee policy and the policies it superseded, keyed by version
+    pub fee_policy_version: Option<u32>,
+    pub fee_policy_history: Option<HashMap<u32, FeePolicy>>,
+    // How far receipt fees may drift from the policy before settlement rejects or flags them
+    pub fee_tolerance: Option<FeeTolerance>,
+    // Issued cost quotes, keyed by quote_id
+    pub quotes: Option<HashMap<String, StoredQuote>>,
+    // Principals allowed to settle, keyed by principal text
+    pub settlers: Option<HashMap<String, Settler>>,
+    // Agents receipts may pay, keyed by agent_id
+    pub agents: Option<HashMap<String, AgentRegistration>>,
+    // How long payers may dispute a settled receipt, in nanoseconds; agent payouts are held meanwhile
+    pub dispute_window: Option<u64>,
+    // Principals besides admins who may resolve disputes
+    pub arbiters: Option<Vec<String>>,
+    // Outcome of the last escrow expiry sweep
+    pub sweeper_status: Option<SweeperStatus>,
     pub metrics: EconMetrics,
     // Governance/admins
     pub admins: Vec<String>,
@@ -39,8 +69,32 @@ pub struct EconState {
     pub state_version: u32,
     // Subscription map
     pub subscriptions: HashMap<String, Subscription>,
+    // Live subscription tier catalog keyed by tier id, and the versions each tier superseded
+    pub tier_catalog: Option<HashMap<String, TierConfig>>,
+    pub tier_history: Option<HashMap<String, Vec<TierConfig>>>,
+    // Recurring billing settings and the outcome of the last billing run
+    pub billing_policy: Option<BillingPolicy>,
+    pub billing_status: Option<BillingRunStatus>,
+    // Finished billing periods' usage per principal, oldest first
+    pub usage_history: Option<HashMap<String, Vec<UsagePeriod>>>,
+    // Open token quota reservations, keyed by reservation_id
+    pub quota_reservations: Option<HashMap<String, QuotaReservation>>,
     // Payment transactions
     pub payment_transactions: Option<HashMap<String, payment::PaymentTransaction>>,
+    // Double-entry journal backing every balance change
+    pub journal: Option<journal::Journal>,
+    // Fee sinks sharing the protocol fee with the treasury
+    pub fee_sinks: Option<Vec<FeeSink>>,
+    // ICRC-1 ledger backing deposits; falls back to the ICP ledger when unset
+    pub ledger_canister_id: Option<String>,
+    // Issued subscription payment quotes, keyed by payment memo
+    pub payment_requests: Option<HashMap<String, payment::PaymentRequest>>,
+    // Ledger account receiving subscription payments
+    pub treasury_account: Option<crate::infra::ledger::Account>,
+    // Withdrawals and deposit sweeps whose ledger call was rejected, keyed by journal reference
+    pub pending_transfers: Option<HashMap<String, balance::PendingTransfer>>,
+    // ICRC-2 pulls not yet used by the flow that requested them, keyed by that flow's reference
+    pub pending_pulls: Option<HashMap<String, balance::PendingPull>>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, CandidType)]
@@ -52,12 +106,34 @@ pub struct EconMetrics {
     pub last_activity: u64,
 }
 
+impl EconState {
+    pub fn fee_policy(&self) -> FeePolicy {
+        self.fee_policy.clone().unwrap_or_default()
+    }
+
+    pub fn fee_policy_version(&self) -> u32 {
+        self.fee_policy_version.unwrap_or(1)
+    }
+
+    pub fn billing_policy(&self) -> BillingPolicy {
+        self.billing_policy.clone().unwrap_or_default()
+    }
+
+    /// The fee policy that was in force as `version`
+    pub fn fee_policy_at(&self, version: u32) -> Option<FeePolicy> {
+        if version == self.fee_policy_version() {
+            return Some(self.fee_policy());
+        }
+        self.fee_policy_history.as_ref().and_then(|history| history.get(&version).cloned())
+    }
+}
+
 pub fn with_state<R>(f: impl FnOnce(&EconState) -> R) -> R {
-    STATE.with(|s| f(&*s.borrow()))
+    STATE.with(|s| f(&s.borrow()))
 }
 
 pub fn with_state_mut<R>(f: impl FnOnce(&mut EconState) -> R) -> R {
-    STATE.with(|s| f(&mut *s.borrow_mut()))
+    STATE.with(|s| f(&mut s.borrow_mut()))
 }
 
 pub fn get_state_clone() -> EconState {
@@ -86,6 +162,13 @@ pub fn list_admins() -> Vec<String> {
     with_state(|state| state.admins.clone())
 }
 
+pub fn set_ledger_canister(principal_text: String) {
+    with_state_mut(|state| {
+        state.ledger_canister_id = Some(principal_text);
+        state.metrics.last_activity = time();
+    });
+}
+
 pub fn remove_admin(principal_text: String) {
     with_state_mut(|state| {
         state.admins.retain(|p| p != &principal_text);
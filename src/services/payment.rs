@@ -1,63 +1,69 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut, SubscriptionService};
+use crate::services::{with_state, with_state_mut, BalanceService, JournalService, SubscriptionService, TreasuryService};
+use crate::infra::{InFlightLock, LedgerClient};
+use crate::infra::ledger::Account;
 use candid::{CandidType, Principal};
-use ic_cdk::api::time;
-// Simplified ICP ledger types for compatibility
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+use crate::infra::runtime::time;
+use sha2::{Sha224, Digest};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// ICP ledger account identifier: CRC32 checksum followed by
+/// SHA-224("\x0Aaccount-id" || principal || subaccount)
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType, PartialEq, Eq)]
 pub struct AccountIdentifier(pub Vec<u8>);
 
 impl AccountIdentifier {
-    pub fn from_hex(hex: &str) -> Result<Self, String> {
-        // Simplified hex parsing - in production would use proper hex crate
-        let bytes = hex.as_bytes().to_vec();
-        Ok(AccountIdentifier(bytes))
+    const DOMAIN_SEPARATOR: &'static [u8] = b"\x0Aaccount-id";
+
+    pub fn new(owner: &Principal, subaccount: Option<&[u8; 32]>) -> Self {
+        let mut hasher = Sha224::new();
+        hasher.update(Self::DOMAIN_SEPARATOR);
+        hasher.update(owner.as_slice());
+        hasher.update(subaccount.unwrap_or(&[0u8; 32]));
+        let hash = hasher.finalize();
+
+        let mut bytes = Vec::with_capacity(32);
+        bytes.extend_from_slice(&crc32fast::hash(&hash).to_be_bytes());
+        bytes.extend_from_slice(&hash);
+        AccountIdentifier(bytes)
     }
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
-pub struct Memo(pub u64);
+    pub fn from_account(account: &Account) -> Result<Self, String> {
+        let subaccount = account.subaccount
+            .as_ref()
+            .map(|s| Self::subaccount_from_slice(s))
+            .transpose()?;
+        Ok(Self::new(&account.owner, subaccount.as_ref()))
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
-pub struct Tokens {
-    pub e8s: u64,
-}
+    /// Parse a 64-character hex account identifier and verify its checksum
+    pub fn from_hex(hex_str: &str) -> Result<Self, String> {
+        let bytes = hex::decode(hex_str.trim()).map_err(|e| format!("Invalid hex: {}", e))?;
+        if bytes.len() != 32 {
+            return Err(format!("Account identifier must be 32 bytes, got {}", bytes.len()));
+        }
 
-impl Tokens {
-    pub fn from_e8s(e8s: u64) -> Self {
-        Tokens { e8s }
-    }
-}
+        let expected = crc32fast::hash(&bytes[4..]).to_be_bytes();
+        if bytes[..4] != expected {
+            return Err("Account identifier checksum mismatch".to_string());
+        }
 
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
-pub struct TransferArgs {
-    pub to: AccountIdentifier,
-    pub fee: Tokens,
-    pub memo: Memo,
-    pub from_subaccount: Option<Vec<u8>>,
-    pub created_at_time: Option<u64>,
-    pub amount: Tokens,
-}
+        Ok(AccountIdentifier(bytes))
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
-pub enum TransferResult {
-    Ok(u64),
-    Err(TransferError),
-}
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
-pub struct TransferError {
-    pub message: String,
+    pub fn subaccount_from_slice(bytes: &[u8]) -> Result<[u8; 32], String> {
+        <[u8; 32]>::try_from(bytes).map_err(|_| format!("Subaccount must be 32 bytes, got {}", bytes.len()))
+    }
 }
 
-const DEFAULT_FEE: Tokens = Tokens { e8s: 10_000 };
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-
 /// Payment service for handling ICP payments through OISY wallet
 pub struct PaymentService;
 
-/// ICP Ledger canister ID
-const ICP_LEDGER_CANISTER_ID: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
 
 /// Payment request for subscription
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
@@ -67,6 +73,10 @@ pub struct PaymentRequest {
     pub amount_icp_e8s: u64,
     pub user_principal: String,
     pub payment_memo: String,
+    // Set when the payment buys a mid-period upgrade rather than a subscription period
+    pub tier_change: Option<TierChange>,
+    // Upgrade quotes lapse so a stale price cannot be paid
+    pub expires_at: Option<u64>,
 }
 
 /// Payment transaction record
@@ -105,19 +115,26 @@ pub struct PaymentVerification {
 }
 
 impl PaymentService {
-    /// Get current ICP/USD exchange rate (simplified - in production would use oracle)
-    pub fn get_icp_usd_rate() -> Result<f64, String> {
+    const TIER_CHANGE_REQUEST_TTL: u64 = 60 * 60 * 1_000_000_000; // 1 hour
+
+    /// Get current ICP/USD exchange rate as USD e8s per ICP (simplified - in production would use oracle)
+    pub fn get_icp_usd_rate_e8s() -> Result<u64, String> {
         // Simplified rate - in production this would come from a price oracle
         // Using approximate rate of 1 ICP = $10 USD
-        Ok(10.0)
+        Ok(10 * Money::E8S_PER_TOKEN)
+    }
+
+    /// Exchange rate for display only; amounts are always computed from the fixed-point rate
+    pub fn get_icp_usd_rate() -> Result<f64, String> {
+        let rate_e8s = Self::get_icp_usd_rate_e8s()?;
+        Ok(rate_e8s as f64 / Money::E8S_PER_TOKEN as f64)
     }
 
-    /// Convert USD amount to ICP e8s (1 ICP = 100,000,000 e8s)
+    /// Convert USD amount to ICP e8s (1 ICP = 100,000,000 e8s), rounded up to the next e8
     pub fn usd_to_icp_e8s(amount_usd: u32) -> Result<u64, String> {
-        let icp_rate = Self::get_icp_usd_rate()?;
-        let icp_amount = amount_usd as f64 / icp_rate;
-        let e8s_amount = (icp_amount * 100_000_000.0) as u64;
-        Ok(e8s_amount)
+        let icp_rate = Self::get_icp_usd_rate_e8s()?;
+        let amount = Money::from_usd(amount_usd, icp_rate)?;
+        Ok(amount.e8s())
     }
 
     /// Create a payment request for subscription
@@ -126,9 +143,7 @@ impl PaymentService {
         subscription_tier: String,
     ) -> Result<PaymentRequest, String> {
         // Get tier configuration
-        let tier_configs = SubscriptionService::get_tier_configs();
-        let tier_config = tier_configs.get(&subscription_tier)
-            .ok_or("Invalid subscription tier")?;
+        let tier_config = SubscriptionService::get_tier_config(&subscription_tier)?;
 
         // Free tier doesn't require payment
         if tier_config.monthly_fee_usd == 0 {
@@ -139,7 +154,8 @@ impl PaymentService {
         let amount_icp_e8s = Self::usd_to_icp_e8s(tier_config.monthly_fee_usd)?;
 
         // Create payment memo
-        let payment_memo = format!("OHMS-{}-{}", subscription_tier.to_uppercase(), time());
+        // Unique per principal, as it keys both the request and its ledger pull
+        let payment_memo = format!("OHMS-{}-{}-{}", subscription_tier.to_uppercase(), user_principal, time());
 
         let payment_request = PaymentRequest {
             subscription_tier,
@@ -147,114 +163,301 @@ impl PaymentService {
             amount_icp_e8s,
             user_principal,
             payment_memo,
+            tier_change: None,
+            expires_at: None,
+        };
+
+        // Remember the quote so payment pulls exactly this amount
+        with_state_mut(|state| {
+            state.payment_requests
+                .get_or_insert_with(HashMap::new)
+                .insert(payment_request.payment_memo.clone(), payment_request.clone());
+        });
+
+        Ok(payment_request)
+    }
+
+    /// Create a payment request for the prorated charge of a tier upgrade
+    pub async fn create_tier_change_request(
+        user_principal: String,
+        change: &TierChange,
+    ) -> Result<PaymentRequest, String> {
+        let from = SubscriptionService::get_user_subscription(&user_principal).ok_or("No subscription found")?;
+        let to = SubscriptionService::get_tier_config(&change.to_tier_id)?;
+
+        // Whole dollars for display only; the e8s amount is what gets charged
+        let difference_usd = to.monthly_fee_usd.saturating_sub(from.tier.monthly_fee_usd) as u128;
+        let period = change.period_ns.max(1) as u128;
+        let amount_usd = (difference_usd * change.remaining_ns as u128).div_ceil(period) as u32;
+
+        // The prorated price only holds for the period it was quoted in
+        let now = time();
+        let expires_at = now.saturating_add(Self::TIER_CHANGE_REQUEST_TTL).min(from.expires_at);
+        let payment_memo = format!("OHMS-UPGRADE-{}-{}-{}", change.to_tier_id.to_uppercase(), user_principal, now);
+        let payment_request = PaymentRequest {
+            subscription_tier: change.to_tier_id.clone(),
+            amount_usd,
+            amount_icp_e8s: change.prorated_amount_e8s,
+            user_principal,
+            payment_memo,
+            tier_change: Some(change.clone()),
+            expires_at: Some(expires_at),
         };
 
+        with_state_mut(|state| {
+            let requests = state.payment_requests.get_or_insert_with(HashMap::new);
+            requests.retain(|_, request| request.expires_at.is_none_or(|at| at > now));
+            requests.insert(payment_request.payment_memo.clone(), payment_request.clone());
+        });
+
         Ok(payment_request)
     }
 
-    /// Process ICP payment through ledger
-    pub async fn process_icp_payment(
+    /// Process a subscription payment by pulling the quoted amount with ICRC-2 `transfer_from`.
+    /// The user must first approve this canister for the amount plus the ledger fee.
+    pub async fn process_subscription_payment(
         payment_request: PaymentRequest,
         from_principal: Principal,
     ) -> Result<PaymentTransaction, String> {
-        let transaction_id = format!("tx_{}", time());
-        
-        // Create initial transaction record
+        // Only requests we issued are payable, and only for the quoted amount
+        let issued = with_state(|state| {
+            state.payment_requests.as_ref()
+                .and_then(|requests| requests.get(&payment_request.payment_memo))
+                .cloned()
+        }).ok_or("Unknown or already used payment request")?;
+
+        if issued.user_principal != from_principal.to_text() {
+            return Err("Payment request belongs to another principal".to_string());
+        }
+
+        if issued.amount_icp_e8s != payment_request.amount_icp_e8s
+            || issued.subscription_tier != payment_request.subscription_tier
+        {
+            return Err("Payment request does not match the issued quote".to_string());
+        }
+
+        let _lock = InFlightLock::acquire(format!("payment:{}", issued.payment_memo))?;
+
+        // A pull already under way must be finished whatever has changed since it was sent
+        if BalanceService::get_pending_pull(&issued.payment_memo).is_none() {
+            if issued.expires_at.is_some_and(|at| at <= time()) {
+                with_state_mut(|state| {
+                    if let Some(requests) = state.payment_requests.as_mut() {
+                        requests.remove(&issued.payment_memo);
+                    }
+                });
+                return Err("Payment request has expired; request a new quote".to_string());
+            }
+
+            // Do not take payment for a subscription or upgrade that could no longer be applied
+            match issued.tier_change.as_ref() {
+                Some(change) => SubscriptionService::validate_tier_upgrade(&issued.user_principal, change)?,
+                None => Self::validate_subscription_tier(&issued.user_principal, &issued.subscription_tier)?,
+            }
+        }
+
+        let now = time();
+        let sequence = Self::transaction_count(&issued.user_principal);
         let mut transaction = PaymentTransaction {
-            id: transaction_id.clone(),
-            user_principal: payment_request.user_principal.clone(),
-            subscription_tier: payment_request.subscription_tier.clone(),
-            amount_usd: payment_request.amount_usd,
-            amount_icp_e8s: payment_request.amount_icp_e8s,
+            id: format!("tx_{}_{}_{}", now, issued.user_principal, sequence),
+            user_principal: issued.user_principal.clone(),
+            subscription_tier: issued.subscription_tier.clone(),
+            amount_usd: issued.amount_usd,
+            amount_icp_e8s: issued.amount_icp_e8s,
             icp_block_index: None,
             status: PaymentTransactionStatus::Processing,
-            memo: payment_request.payment_memo.clone(),
-            created_at: time(),
+            memo: issued.payment_memo.clone(),
+            created_at: now,
             completed_at: None,
             error_message: None,
         };
 
         // Store transaction in pending state
-        with_state_mut(|state| {
-            if state.payment_transactions.is_none() {
-                state.payment_transactions = Some(HashMap::new());
-            }
-            state.payment_transactions.as_mut().unwrap()
-                .insert(transaction_id.clone(), transaction.clone());
-        });
+        Self::store_transaction(&transaction);
 
-        // Get OHMS treasury account (in production this would be a proper treasury account)
-        let treasury_account = AccountIdentifier::from_hex("2c4449a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8")
-            .map_err(|e| format!("Invalid treasury account: {}", e))?;
+        match Self::pull_payment(&issued.payment_memo, from_principal, issued.amount_icp_e8s, JournalEntryKind::SubscriptionPayment).await {
+            Ok(block_index) => {
+                // Payment successful
+                transaction.status = PaymentTransactionStatus::Completed;
+                transaction.icp_block_index = Some(block_index);
+                transaction.completed_at = Some(time());
 
-        // Create transfer arguments
-        let transfer_args = TransferArgs {
-            memo: Memo(0), // Could encode payment info here
-            amount: Tokens::from_e8s(payment_request.amount_icp_e8s),
-            fee: DEFAULT_FEE,
-            from_subaccount: None,
-            to: treasury_account,
-            created_at_time: None,
-        };
+                BalanceService::complete_pull(&issued.payment_memo);
+                with_state_mut(|state| {
+                    state.payment_requests.get_or_insert_with(HashMap::new).remove(&issued.payment_memo);
+                });
 
-        // Call ICP ledger for transfer
-        let ledger_principal = Principal::from_text(ICP_LEDGER_CANISTER_ID)
-            .map_err(|e| format!("Invalid ledger principal: {}", e))?;
-
-        match ic_cdk::call::<(TransferArgs,), (TransferResult,)>(ledger_principal, "transfer", (transfer_args,)).await {
-            Ok((transfer_result,)) => {
-                match transfer_result {
-                    TransferResult::Ok(block_index) => {
-                        // Payment successful
-                        transaction.status = PaymentTransactionStatus::Completed;
-                        transaction.icp_block_index = Some(block_index);
-                        transaction.completed_at = Some(time());
-
-                        // Update subscription payment status
-                        if let Err(e) = SubscriptionService::update_payment_status(
-                            payment_request.user_principal.clone(),
-                            crate::domain::PaymentStatus::Active,
-                        ).await {
-                            transaction.error_message = Some(format!("Failed to update subscription: {}", e));
-                        }
-
-                        // Store completed transaction
-                        with_state_mut(|state| {
-                            state.payment_transactions.as_mut().unwrap()
-                                .insert(transaction_id, transaction.clone());
-                        });
-
-                        Ok(transaction)
-                    }
-                    TransferResult::Err(transfer_error) => {
-                        // Payment failed
-                        transaction.status = PaymentTransactionStatus::Failed;
-                        transaction.error_message = Some(format!("Transfer failed: {:?}", transfer_error));
-                        transaction.completed_at = Some(time());
-
-                        with_state_mut(|state| {
-                            state.payment_transactions.as_mut().unwrap()
-                                .insert(transaction_id, transaction.clone());
-                        });
-
-                        Err(format!("Payment failed: {:?}", transfer_error))
-                    }
+                // Apply the paid upgrade, or mark the subscription period paid
+                let updated = match &issued.tier_change {
+                    Some(change) => SubscriptionService::apply_tier_upgrade(&issued.user_principal, change).map(|_| ()),
+                    None => SubscriptionService::update_payment_status(
+                        issued.user_principal.clone(),
+                        crate::domain::PaymentStatus::Active,
+                    ).await,
+                };
+                if let Err(e) = updated {
+                    transaction.error_message = Some(format!("Failed to update subscription: {}", e));
                 }
+
+                // Store completed transaction
+                Self::store_transaction(&transaction);
+
+                Ok(transaction)
             }
-            Err((rejection_code, rejection_message)) => {
-                // Call to ledger failed
-                transaction.status = PaymentTransactionStatus::Failed;
-                transaction.error_message = Some(format!("Ledger call failed: {} - {}", rejection_code as u8, rejection_message));
+            Err(e) => {
+                // An unknown outcome leaves the pull pending until the same request is paid again
+                transaction.status = if BalanceService::get_pending_pull(&issued.payment_memo).is_some() {
+                    PaymentTransactionStatus::Pending
+                } else {
+                    PaymentTransactionStatus::Failed
+                };
+                transaction.error_message = Some(e.clone());
                 transaction.completed_at = Some(time());
 
-                with_state_mut(|state| {
-                    state.payment_transactions.as_mut().unwrap()
-                        .insert(transaction_id, transaction.clone());
-                });
+                Self::store_transaction(&transaction);
+
+                Err(format!("Payment failed: {}", e))
+            }
+        }
+    }
+
+    /// A subscription period can only be paid for the tier the subscription is on
+    fn validate_subscription_tier(user_principal: &str, subscription_tier: &str) -> Result<(), String> {
+        let subscription = SubscriptionService::get_user_subscription(user_principal)
+            .ok_or("No subscription found; create one before paying")?;
+        let current = subscription.tier_id.clone().unwrap_or_else(|| subscription.tier.name.to_lowercase());
+        if current != subscription_tier {
+            return Err(format!(
+                "Payment request is for tier {} but the subscription is on {}",
+                subscription_tier, current
+            ));
+        }
+        Ok(())
+    }
+
+    /// Pull a payment into the treasury account. Payments into this canister's own account are
+    /// held in custody and journaled; an external treasury account receives them directly.
+    async fn pull_payment(reference: &str, from: Principal, amount_icp_e8s: u64, kind: JournalEntryKind) -> Result<u64, String> {
+        let treasury = TreasuryService::treasury_account();
+        let held_in_custody = treasury == LedgerClient::canister_account(None);
+        BalanceService::pull(
+            reference,
+            from,
+            treasury,
+            amount_icp_e8s,
+            held_in_custody.then_some(JournalAccount::ProtocolTreasury),
+            kind,
+        ).await
+    }
+
+    /// Charge the renewal of the period ending at `period_end`, drawing on the user's econ balance
+    /// first and falling back to an ICRC-2 allowance for this canister. A pull left pending by an
+    /// earlier attempt is retried as it was sent, so the charged amount can differ from
+    /// `amount_icp_e8s`; the returned transaction carries what was actually charged.
+    pub async fn charge_renewal(
+        user_principal: &str,
+        subscription_tier: &str,
+        amount_usd: u32,
+        amount_icp_e8s: u64,
+        period_end: u64,
+    ) -> Result<PaymentTransaction, String> {
+        // Fixed per period, so every attempt at this renewal shares one ledger pull
+        let memo = format!("OHMS-RENEWAL-{}-{}-{}", subscription_tier.to_uppercase(), user_principal, period_end);
+        Self::charge(user_principal, subscription_tier, amount_usd, amount_icp_e8s, memo, JournalEntryKind::SubscriptionPayment).await
+    }
+
+    /// Collect overage left owing on the subscription whose last period ended at `period_end`, the
+    /// same way a renewal is charged
+    pub async fn charge_overage(
+        user_principal: &str,
+        subscription_tier: &str,
+        amount_icp_e8s: u64,
+        period_end: u64,
+    ) -> Result<PaymentTransaction, String> {
+        let memo = format!("OHMS-OVERAGE-{}-{}-{}", subscription_tier.to_uppercase(), user_principal, period_end);
+        Self::charge(user_principal, subscription_tier, 0, amount_icp_e8s, memo, JournalEntryKind::OverageCharge).await
+    }
+
+    async fn charge(
+        user_principal: &str,
+        subscription_tier: &str,
+        amount_usd: u32,
+        amount_icp_e8s: u64,
+        memo: String,
+        kind: JournalEntryKind,
+    ) -> Result<PaymentTransaction, String> {
+        let now = time();
+        // Several subscriptions renew within one billing run, so the time alone is not unique
+        let sequence = Self::transaction_count(user_principal);
+        let pending = BalanceService::get_pending_pull(&memo);
+        let mut transaction = PaymentTransaction {
+            id: format!("tx_{}_{}_{}", now, user_principal, sequence),
+            user_principal: user_principal.to_string(),
+            subscription_tier: subscription_tier.to_string(),
+            amount_usd,
+            amount_icp_e8s: pending.as_ref().map(|pull| pull.amount).unwrap_or(amount_icp_e8s),
+            icp_block_index: None,
+            status: PaymentTransactionStatus::Processing,
+            memo: memo.clone(),
+            created_at: now,
+            completed_at: None,
+            error_message: None,
+        };
+
+        // Never pay from the balance while a pull for this period may still land
+        let from_balance = match pending {
+            Some(_) => Err("Renewal pull pending".to_string()),
+            None => with_state_mut(|state| {
+                let mut postings = JournalService::spend_postings(state, user_principal, amount_icp_e8s)?;
+                postings.push(Posting::credit(JournalAccount::ProtocolTreasury, amount_icp_e8s));
+                JournalService::post(state, kind.clone(), &memo, postings)
+            }),
+        };
+
+        if from_balance.is_err() {
+            let from = Principal::from_text(user_principal).map_err(|e| format!("Invalid principal: {}", e))?;
+            Self::store_transaction(&transaction);
 
-                Err(format!("Ledger call failed: {}", rejection_message))
+            match Self::pull_payment(&memo, from, amount_icp_e8s, kind).await {
+                Ok(block_index) => {
+                    transaction.icp_block_index = Some(block_index);
+                    BalanceService::complete_pull(&memo);
+                }
+                Err(e) => {
+                    transaction.status = if BalanceService::get_pending_pull(&memo).is_some() {
+                        PaymentTransactionStatus::Pending
+                    } else {
+                        PaymentTransactionStatus::Failed
+                    };
+                    transaction.error_message = Some(e.clone());
+                    transaction.completed_at = Some(time());
+                    Self::store_transaction(&transaction);
+                    return Err(format!("Payment failed: {}", e));
+                }
             }
         }
+
+        transaction.status = PaymentTransactionStatus::Completed;
+        transaction.completed_at = Some(time());
+        Self::store_transaction(&transaction);
+        Ok(transaction)
+    }
+
+    fn store_transaction(transaction: &PaymentTransaction) {
+        with_state_mut(|state| {
+            state.payment_transactions
+                .get_or_insert_with(HashMap::new)
+                .insert(transaction.id.clone(), transaction.clone());
+        });
+    }
+
+    /// Payment transactions recorded for `user_principal` so far
+    fn transaction_count(user_principal: &str) -> u64 {
+        with_state(|state| {
+            state.payment_transactions.as_ref()
+                .map(|txs| txs.values().filter(|tx| tx.user_principal == user_principal).count() as u64)
+                .unwrap_or(0)
+        })
     }
 
     /// Verify payment transaction
@@ -296,7 +499,7 @@ impl PaymentService {
                         .collect();
                     
                     // Sort by creation time (newest first)
-                    transactions.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                    transactions.sort_by_key(|tx| std::cmp::Reverse(tx.created_at));
                     
                     // Apply limit
                     transactions.into_iter().take(limit as usize).collect()
@@ -350,4 +553,88 @@ pub struct PaymentStats {
     pub pending_transactions: u32,
     pub total_revenue_usd: u32,
     pub total_revenue_icp_e8s: u64,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `dfx ledger account-id` for the anonymous principal
+    const ANONYMOUS_ACCOUNT_ID: &str = "1c7a48ba6a562aa9eaa2481a9049cdf0433b9738c992d698c31d8abf89cadc79";
+
+    #[test]
+    fn derives_known_account_identifier() {
+        let account_id = AccountIdentifier::new(&Principal::anonymous(), None);
+        assert_eq!(account_id.to_hex(), ANONYMOUS_ACCOUNT_ID);
+    }
+
+    #[test]
+    fn default_and_zero_subaccount_match() {
+        let zero = Account { owner: Principal::anonymous(), subaccount: Some(vec![0; 32]) };
+        assert_eq!(AccountIdentifier::from_account(&zero).unwrap().to_hex(), ANONYMOUS_ACCOUNT_ID);
+
+        let mut subaccount = [0u8; 32];
+        subaccount[0] = 1;
+        let other = AccountIdentifier::new(&Principal::anonymous(), Some(&subaccount));
+        assert_ne!(other.to_hex(), ANONYMOUS_ACCOUNT_ID);
+    }
+
+    #[test]
+    fn checksum_is_crc32_of_the_hash() {
+        let account_id = AccountIdentifier::new(&Principal::anonymous(), None);
+        assert_eq!(account_id.0.len(), 32);
+        assert_eq!(account_id.0[..4], crc32fast::hash(&account_id.0[4..]).to_be_bytes());
+    }
+
+    #[test]
+    fn from_hex_round_trips_and_verifies_checksum() {
+        let parsed = AccountIdentifier::from_hex(ANONYMOUS_ACCOUNT_ID).unwrap();
+        assert_eq!(parsed, AccountIdentifier::new(&Principal::anonymous(), None));
+
+        let corrupted = format!("00{}", &ANONYMOUS_ACCOUNT_ID[2..]);
+        assert_eq!(AccountIdentifier::from_hex(&corrupted), Err("Account identifier checksum mismatch".to_string()));
+        assert!(AccountIdentifier::from_hex("abcd").is_err());
+    }
+
+    #[test]
+    fn rejects_short_subaccount() {
+        let account = Account { owner: Principal::anonymous(), subaccount: Some(vec![1; 31]) };
+        assert!(AccountIdentifier::from_account(&account).is_err());
+    }
+
+    #[test]
+    fn retried_renewal_reuses_the_pending_pull() {
+        use crate::infra::ledger::ApproveArgs;
+        use crate::infra::mock_ledger::{MockFault, MockLedger};
+        use crate::infra::runtime::{self, block_on};
+        use candid::Nat;
+
+        MockLedger::reset();
+        runtime::set_time(1_000_000_000);
+        let user = Principal::from_slice(&[1; 10]);
+        let wallet = Account { owner: user, subaccount: None };
+        MockLedger::mint(wallet.clone(), 1_010_000);
+        MockLedger::icrc2_approve(user, ApproveArgs {
+            from_subaccount: None,
+            spender: LedgerClient::canister_account(None),
+            amount: Nat::from(1_000_000u64),
+            expected_allowance: None,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }).unwrap();
+
+        MockLedger::inject_fault(MockFault::LoseReply);
+        let renew = |amount| block_on(PaymentService::charge_renewal(&user.to_text(), "basic", 10, amount, 5_000));
+        assert!(renew(300_000).is_err());
+        runtime::advance_time(1);
+
+        // Overage accrued since then does not change the pull already sent for this period
+        let charged = renew(350_000).unwrap();
+        assert_eq!(charged.amount_icp_e8s, 300_000);
+        assert_eq!(MockLedger::icrc1_balance_of(wallet), Nat::from(1_000_000u64 - 300_000 - 10_000));
+        assert_eq!(JournalService::account_balance(&JournalAccount::ProtocolTreasury), 300_000);
+        assert!(BalanceService::list_pending_pulls(None).is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::domain::*;
+use crate::services::{with_state, with_state_mut};
+use crate::infra::ReceiptSignature;
+use candid::Principal;
+use crate::infra::runtime::{time, caller};
+use std::collections::HashMap;
+
+/// Registry of principals allowed to settle receipts and of agents receipts may pay
+pub struct RegistryService;
+
+impl RegistryService {
+    pub fn register_settler(principal: Principal, role: SettlerRole) -> Result<(), String> {
+        if principal == Principal::anonymous() {
+            return Err("Settler cannot be anonymous".to_string());
+        }
+
+        let principal_id = principal.to_text();
+        with_state_mut(|state| {
+            state.settlers.get_or_insert_with(HashMap::new).insert(principal_id.clone(), Settler {
+                principal_id,
+                role,
+                registered_at: time(),
+            });
+            state.metrics.last_activity = time();
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_settler(principal_id: &str) -> Result<(), String> {
+        with_state_mut(|state| {
+            state.settlers
+                .as_mut()
+                .and_then(|settlers| settlers.remove(principal_id))
+                .ok_or_else(|| format!("Settler not found: {}", principal_id))?;
+            state.metrics.last_activity = time();
+            Ok(())
+        })
+    }
+
+    pub fn list_settlers() -> Vec<Settler> {
+        with_state(|state| {
+            state.settlers
+                .as_ref()
+                .map(|settlers| settlers.values().cloned().collect())
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn register_agent(agent_id: String, payout_principal: Principal) -> Result<(), String> {
+        if agent_id.is_empty() {
+            return Err("Agent ID cannot be empty".to_string());
+        }
+        if payout_principal == Principal::anonymous() {
+            return Err("Payout principal cannot be anonymous".to_string());
+        }
+
+        with_state_mut(|state| {
+            let agents = state.agents.get_or_insert_with(HashMap::new);
+            // Re-registering changes the payout principal but keeps the signing key
+            let public_key = agents.get(&agent_id).and_then(|agent| agent.public_key.clone());
+            agents.insert(agent_id.clone(), AgentRegistration {
+                agent_id,
+                payout_principal: payout_principal.to_text(),
+                registered_at: time(),
+                public_key,
+            });
+            state.metrics.last_activity = time();
+        });
+
+        Ok(())
+    }
+
+    /// Set the Ed25519 key an agent signs receipts with; callable by an admin or the agent's payout principal
+    pub fn set_agent_key(agent_id: String, public_key: Vec<u8>) -> Result<(), String> {
+        ReceiptSignature::parse_public_key(&public_key)?;
+        let caller_text = caller().to_text();
+
+        with_state_mut(|state| {
+            let is_admin = state.admins.iter().any(|p| p == &caller_text);
+            let agent = state.agents
+                .as_mut()
+                .and_then(|agents| agents.get_mut(&agent_id))
+                .ok_or_else(|| format!("Agent not registered: {}", agent_id))?;
+            if !is_admin && agent.payout_principal != caller_text {
+                return Err("Only an admin or the agent's payout principal can set its key".to_string());
+            }
+
+            agent.public_key = Some(public_key);
+            state.metrics.last_activity = time();
+            Ok(())
+        })
+    }
+
+    pub fn remove_agent(agent_id: &str) -> Result<(), String> {
+        with_state_mut(|state| {
+            state.agents
+                .as_mut()
+                .and_then(|agents| agents.remove(agent_id))
+                .ok_or_else(|| format!("Agent not found: {}", agent_id))?;
+            state.metrics.last_activity = time();
+            Ok(())
+        })
+    }
+
+    pub fn get_agent(agent_id: &str) -> Result<AgentRegistration, String> {
+        with_state(|state| {
+            state.agents
+                .as_ref()
+                .and_then(|agents| agents.get(agent_id))
+                .cloned()
+                .ok_or_else(|| format!("Agent not registered: {}", agent_id))
+        })
+    }
+
+    pub fn list_agents() -> Vec<AgentRegistration> {
+        with_state(|state| {
+            state.agents
+                .as_ref()
+                .map(|agents| agents.values().cloned().collect())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Let a settler settle one of the caller's escrows; restricts the escrow to its listed settlers
+    pub fn authorize_escrow_settler(escrow_id: String, settler: Principal) -> Result<(), String> {
+        let caller_text = caller().to_text();
+        let settler_id = settler.to_text();
+
+        with_state_mut(|state| {
+            if !state.settlers.as_ref().is_some_and(|settlers| settlers.contains_key(&settler_id)) {
+                return Err(format!("Settler not registered: {}", settler_id));
+            }
+
+            let escrow = state.escrows
+                .get_mut(&escrow_id)
+                .ok_or_else(|| "Escrow not found".to_string())?;
+            if escrow.principal_id != caller_text {
+                return Err("Only the escrow holder can authorize settlers".to_string());
+            }
+            if !matches!(escrow.status, EscrowStatus::Active) {
+                return Err("Escrow is not active".to_string());
+            }
+
+            let settlers = escrow.settlers.get_or_insert_with(Vec::new);
+            if !settlers.contains(&settler_id) {
+                settlers.push(settler_id);
+            }
+            Ok(())
+        })
+    }
+
+    /// Check that `settler_id` may settle `escrow` paying `agent_id`; returns the agent's registration
+    pub fn authorize_settlement(settler_id: &str, escrow: &EscrowAccount, agent_id: &str) -> Result<AgentRegistration, String> {
+        let settler = Self::get_settler(settler_id)
+            .ok_or_else(|| "Caller is not a registered settler".to_string())?;
+
+        if !Self::settler_allowed(&settler, escrow) {
+            return Err(format!("Caller is not authorized to settle job {}", escrow.job_id));
+        }
+
+        let agent = Self::get_agent(agent_id)?;
+        if settler.role == SettlerRole::Agent && agent.payout_principal != settler_id {
+            return Err("Agents may only settle receipts paying themselves".to_string());
+        }
+
+        Ok(agent)
+    }
+
+    /// Whether `principal_id` is a coordinator allowed to act on `escrow`
+    pub fn is_authorized_coordinator(principal_id: &str, escrow: &EscrowAccount) -> bool {
+        Self::get_settler(principal_id)
+            .is_some_and(|settler| settler.role == SettlerRole::Coordinator && Self::settler_allowed(&settler, escrow))
+    }
+
+    /// Whether the principal is a registered coordinator, the role agent factories run under
+    pub fn is_coordinator(principal_id: &str) -> bool {
+        Self::get_settler(principal_id).is_some_and(|settler| settler.role == SettlerRole::Coordinator)
+    }
+
+    fn get_settler(principal_id: &str) -> Option<Settler> {
+        with_state(|state| state.settlers.as_ref().and_then(|settlers| settlers.get(principal_id)).cloned())
+    }
+
+    // Escrows that list settlers admit only those; otherwise any coordinator but no agent
+    fn settler_allowed(settler: &Settler, escrow: &EscrowAccount) -> bool {
+        let listed = escrow.settlers
+            .as_ref()
+            .map(|settlers| settlers.iter().any(|p| p == &settler.principal_id));
+        match settler.role {
+            SettlerRole::Coordinator => listed.unwrap_or(true),
+            SettlerRole::Agent => listed.unwrap_or(false),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut, EscrowService};
-use ic_cdk::api::time;
+use crate::services::{with_state, with_state_mut, DisputeService, EscrowService, RegistryService};
+use crate::infra::ReceiptSignature;
+use crate::infra::runtime::{time, caller};
 use sha2::{Sha256, Digest};
 use base64::{Engine as _, engine::general_purpose};
 
@@ -22,12 +23,75 @@ impl SettlementService {
             return Err("Escrow is not active".to_string());
         }
         
-        if escrow.amount < receipt.actual_cost {
-            return Err("Insufficient escrow amount for settlement".to_string());
+        if receipt.job_id != escrow.job_id {
+            return Err(format!("Receipt job {} does not match escrow job {}", receipt.job_id, escrow.job_id));
         }
         
-        // Release funds to agent
-        EscrowService::release_escrow(receipt.escrow_id.clone(), receipt.agent_id.clone(), receipt.actual_cost)?;
+        if escrow.remaining() < receipt.actual_cost {
+            return Err("Receipt would overdraw escrow".to_string());
+        }
+        
+        // Only a registered settler allowed on this job may settle, and only to a registered agent
+        let agent = RegistryService::authorize_settlement(&caller().to_text(), &escrow, &receipt.agent_id)?;
+        
+        // The paid agent must have attested the receipt; for multi-agent jobs the lead agent attests the split
+        let public_key = agent.public_key
+            .as_ref()
+            .ok_or_else(|| format!("Agent {} has no registered signing key", agent.agent_id))?;
+        ReceiptSignature::verify(&receipt, public_key)?;
+        
+        let fee_deviation = Self::verify_fees(&receipt, &escrow)?;
+        
+        // Split the settled amount: base + agent fee to the agents, protocol fee to the treasury
+        let fees = &receipt.fees_breakdown;
+        let agent_payout = fees.base_amount
+            .checked_add(fees.agent_fee)
+            .ok_or_else(|| "Agent payout overflow".to_string())?;
+        let protocol_fee = fees.protocol_fee;
+        let distribution = match receipt.shares.as_ref() {
+            Some(shares) => Self::resolve_shares(shares, agent_payout)?,
+            None => vec![(agent.payout_principal.clone(), agent_payout)],
+        };
+        
+        // Hold the agent payout until the payer's dispute window closes
+        let dispute_window = DisputeService::get_dispute_window();
+        let payout_hold = (dispute_window > 0 && agent_payout > 0).then(|| PayoutHold {
+            amount: agent_payout,
+            payee: agent.payout_principal.clone(),
+            release_at: now + dispute_window,
+            released_at: None,
+            shares: receipt.shares.as_ref().map(|_| distribution.clone()),
+        });
+        let payouts = if payout_hold.is_some() {
+            vec![(JournalAccount::PayoutHold(receipt.receipt_id.clone()), agent_payout)]
+        } else {
+            distribution
+                .iter()
+                .map(|(principal_id, amount)| (JournalAccount::AgentEarnings(principal_id.clone()), *amount))
+                .collect()
+        };
+        
+        let refunded = EscrowService::release_escrow(
+            receipt.escrow_id.clone(),
+            &settlement_id,
+            payouts,
+            protocol_fee,
+            receipt.close_escrow.unwrap_or(true),
+        )?;
+        
+        let mut receipt = receipt;
+        receipt.lines = Some(Self::build_receipt_lines(&distribution, protocol_fee, &escrow.principal_id, refunded));
+        receipt.fee_deviation = fee_deviation;
+        receipt.payout_hold = payout_hold;
+        receipt.dispute = None;
+        receipt.settlement_status = SettlementStatus::Completed;
+        receipt.settled_at = Some(now);
+        receipt.status_history = Some(vec![SettlementTransition {
+            status: SettlementStatus::Completed,
+            changed_by: caller().to_text(),
+            changed_at: now,
+            note: None,
+        }]);
         
         // Record settlement
         let settlement_entry = SettlementEntry {
@@ -39,7 +103,6 @@ impl SettlementService {
         };
         
         let receipt_cost = receipt.actual_cost;
-        let protocol_fee = receipt.fees_breakdown.protocol_fee;
         
         let receipt_id_clone = receipt.receipt_id.clone();
         with_state_mut(|state| {
@@ -66,11 +129,11 @@ impl SettlementService {
         })
     }
     
-    pub fn list_receipts(principal_id: &str, limit: u32) -> Vec<Receipt> {
+    pub fn list_receipts(_principal_id: &str, limit: u32) -> Vec<Receipt> {
         with_state(|state| {
             state.receipts
                 .values()
-                .filter(|receipt| {
+                .filter(|_receipt| {
                     // In real implementation, check if principal owns this receipt
                     true
                 })
@@ -80,19 +143,148 @@ impl SettlementService {
         })
     }
     
-    pub fn calculate_fees(base_amount: u64, fee_policy: &FeePolicy) -> FeesBreakdown {
-        let protocol_fee = ((base_amount as f64) * (fee_policy.protocol_fee_percentage as f64 / 100.0)) as u64;
-        let agent_fee = ((base_amount as f64) * (fee_policy.agent_fee_percentage as f64 / 100.0)) as u64;
-        let total_amount = base_amount + protocol_fee + agent_fee;
+    /// Map shares to `(payout principal, amount)` pairs that sum exactly to `agent_payout`.
+    /// Amount shares must add up to the payout; weight shares divide it, rounding dust to the first share.
+    fn resolve_shares(shares: &[PayoutShare], agent_payout: u64) -> Result<Vec<(String, u64)>, String> {
+        const MAX_SHARES: usize = 32;
         
-        FeesBreakdown {
-            base_amount,
-            protocol_fee,
-            agent_fee,
-            total_amount,
+        if shares.is_empty() {
+            return Err("Shares cannot be empty".to_string());
+        }
+        if shares.len() > MAX_SHARES {
+            return Err(format!("At most {} shares are allowed", MAX_SHARES));
+        }
+        
+        let mut payees = Vec::with_capacity(shares.len());
+        for share in shares {
+            payees.push(RegistryService::get_agent(&share.agent_id)?.payout_principal);
+        }
+        
+        let amounts: Vec<u64> = if shares.iter().all(|share| matches!(share.share, ShareSpec::Amount(_))) {
+            let amounts: Vec<u64> = shares
+                .iter()
+                .map(|share| match share.share { ShareSpec::Amount(amount) => amount, ShareSpec::Weight(_) => 0 })
+                .collect();
+            let total = amounts
+                .iter()
+                .try_fold(Money::ZERO, |total, amount| total.checked_add(Money::from_e8s(*amount)))?;
+            if total.e8s() != agent_payout {
+                return Err(format!("Shares sum to {} but the agent payout is {}", total.e8s(), agent_payout));
+            }
+            amounts
+        } else if shares.iter().all(|share| matches!(share.share, ShareSpec::Weight(_))) {
+            let weights: Vec<u64> = shares
+                .iter()
+                .map(|share| match share.share { ShareSpec::Weight(weight) => weight as u64, ShareSpec::Amount(_) => 0 })
+                .collect();
+            if weights.contains(&0) {
+                return Err("Share weights must be greater than zero".to_string());
+            }
+            Money::from_e8s(agent_payout)
+                .split_by_weights(&weights)?
+                .into_iter()
+                .map(Money::e8s)
+                .collect()
+        } else {
+            return Err("Shares must be all amounts or all weights".to_string());
+        };
+        
+        Ok(payees.into_iter().zip(amounts).collect())
+    }
+    
+    fn build_receipt_lines(distribution: &[(String, u64)], protocol_fee: u64, payer: &str, refunded: u64) -> Vec<ReceiptLine> {
+        let mut lines: Vec<ReceiptLine> = distribution
+            .iter()
+            .map(|(principal_id, amount)| ReceiptLine {
+                kind: ReceiptLineKind::AgentPayout,
+                recipient: principal_id.clone(),
+                amount: *amount,
+            })
+            .collect();
+        
+        if protocol_fee > 0 {
+            lines.push(ReceiptLine {
+                kind: ReceiptLineKind::ProtocolFee,
+                recipient: "protocol_treasury".to_string(),
+                amount: protocol_fee,
+            });
+        }
+        
+        if refunded > 0 {
+            lines.push(ReceiptLine {
+                kind: ReceiptLineKind::Refund,
+                recipient: payer.to_string(),
+                amount: refunded,
+            });
+        }
+        
+        lines
+    }
+    
+    /// Recompute the receipt's fees under the policy version its escrow was opened with.
+    /// Within tolerance: `None`. Outside it: an error, or the expected fees when the tolerance flags.
+    fn verify_fees(receipt: &Receipt, escrow: &EscrowAccount) -> Result<Option<FeeDeviation>, String> {
+        let (policy_version, policy, tolerance) = with_state(|state| {
+            let version = escrow.fee_policy_version.unwrap_or_else(|| state.fee_policy_version());
+            (version, state.fee_policy_at(version), state.fee_tolerance.clone().unwrap_or_default())
+        });
+        let policy = policy.ok_or_else(|| format!("Fee policy version {} not found", policy_version))?;
+        
+        let expected = Self::calculate_fees(receipt.fees_breakdown.base_amount, &policy)?;
+        let rate = BasisPoints(tolerance.tolerance_bps);
+        let within = |actual: u64, expected: u64| -> Result<bool, String> {
+            let allowed = Money::from_e8s(expected).apply_bps(rate)?;
+            Ok(actual.abs_diff(expected) <= allowed.e8s())
+        };
+        
+        if within(receipt.fees_breakdown.protocol_fee, expected.protocol_fee)?
+            && within(receipt.fees_breakdown.agent_fee, expected.agent_fee)?
+        {
+            return Ok(None);
+        }
+        
+        match tolerance.action {
+            FeeDeviationAction::Reject => Err(format!(
+                "Receipt fees deviate from fee policy v{}: expected protocol fee {} and agent fee {}, got {} and {}",
+                policy_version,
+                expected.protocol_fee,
+                expected.agent_fee,
+                receipt.fees_breakdown.protocol_fee,
+                receipt.fees_breakdown.agent_fee
+            )),
+            FeeDeviationAction::Flag => {
+                log::warn!("Receipt {} flagged: fees deviate from fee policy v{}", receipt.receipt_id, policy_version);
+                Ok(Some(FeeDeviation { policy_version, expected }))
+            }
         }
     }
     
+    /// Settled receipts whose fees were let through outside the tolerance
+    pub fn list_flagged_receipts(limit: u32) -> Vec<Receipt> {
+        with_state(|state| {
+            state.receipts
+                .values()
+                .filter(|receipt| receipt.fee_deviation.is_some())
+                .take(limit as usize)
+                .cloned()
+                .collect()
+        })
+    }
+    
+    pub fn calculate_fees(base_amount: u64, fee_policy: &FeePolicy) -> Result<FeesBreakdown, String> {
+        let base = Money::from_e8s(base_amount);
+        let protocol_fee = base.apply_bps(fee_policy.protocol_fee_rate())?;
+        let agent_fee = base.apply_bps(fee_policy.agent_fee_rate())?;
+        let total_amount = base.checked_add(protocol_fee)?.checked_add(agent_fee)?;
+        
+        Ok(FeesBreakdown {
+            base_amount,
+            protocol_fee: protocol_fee.e8s(),
+            agent_fee: agent_fee.e8s(),
+            total_amount: total_amount.e8s(),
+        })
+    }
+    
     pub fn verify_settlement_integrity(receipt_id: &str) -> Result<bool, String> {
         with_state(|state| {
             if let Some(receipt) = state.receipts.get(receipt_id) {
@@ -105,7 +297,9 @@ impl SettlementService {
                     let status_consistent = matches!(
                         (&receipt.settlement_status, &settlement.status),
                         (SettlementStatus::Completed, SettlementStatus::Completed) |
-                        (SettlementStatus::Failed, SettlementStatus::Failed)
+                        (SettlementStatus::Failed, SettlementStatus::Failed) |
+                        (SettlementStatus::Disputed, SettlementStatus::Disputed) |
+                        (SettlementStatus::Refunded, SettlementStatus::Refunded)
                     );
                     
                         Ok(amounts_match && status_consistent)
@@ -123,7 +317,7 @@ impl SettlementService {
     
     fn is_duplicate_settlement(receipt_id: &str) -> bool {
         with_state(|state| {
-            state.settlements.contains_key(receipt_id)
+            state.receipt_to_settlement.contains_key(receipt_id)
         })
     }
     
@@ -144,4 +338,70 @@ impl SettlementService {
         let hash = hasher.finalize();
         general_purpose::STANDARD.encode(&hash[..16])
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn register(agent_ids: &[&str]) {
+        with_state_mut(|state| {
+            let agents = state.agents.get_or_insert_with(HashMap::new);
+            for agent_id in agent_ids {
+                agents.insert(agent_id.to_string(), AgentRegistration {
+                    agent_id: agent_id.to_string(),
+                    payout_principal: format!("{}-payout", agent_id),
+                    registered_at: 0,
+                    public_key: None,
+                });
+            }
+        });
+    }
+
+    fn weights(weights: &[(&str, u32)]) -> Vec<PayoutShare> {
+        weights.iter().map(|(agent_id, weight)| PayoutShare { agent_id: agent_id.to_string(), share: ShareSpec::Weight(*weight) }).collect()
+    }
+
+    fn paid(distribution: Vec<(String, u64)>) -> Vec<u64> {
+        distribution.into_iter().map(|(_, amount)| amount).collect()
+    }
+
+    #[test]
+    fn weighted_shares_split_the_payout() {
+        register(&["a", "b", "c"]);
+        let distribution = SettlementService::resolve_shares(&weights(&[("a", 1), ("b", 3)]), 1_000).unwrap();
+        assert_eq!(distribution, vec![("a-payout".to_string(), 250), ("b-payout".to_string(), 750)]);
+
+        let amounts = vec![
+            PayoutShare { agent_id: "a".to_string(), share: ShareSpec::Amount(600) },
+            PayoutShare { agent_id: "c".to_string(), share: ShareSpec::Amount(400) },
+        ];
+        assert_eq!(paid(SettlementService::resolve_shares(&amounts, 1_000).unwrap()), vec![600, 400]);
+        assert!(SettlementService::resolve_shares(&amounts, 999).is_err());
+    }
+
+    #[test]
+    fn rounding_remainder_goes_to_the_first_share() {
+        register(&["a", "b", "c"]);
+        let distribution = SettlementService::resolve_shares(&weights(&[("a", 1), ("b", 1), ("c", 1)]), 100).unwrap();
+        assert_eq!(paid(distribution), vec![34, 33, 33]);
+
+        let distribution = SettlementService::resolve_shares(&weights(&[("c", 2), ("a", 1)]), 10).unwrap();
+        assert_eq!(distribution, vec![("c-payout".to_string(), 7), ("a-payout".to_string(), 3)]);
+    }
+
+    #[test]
+    fn rejects_empty_zero_and_mixed_shares() {
+        register(&["a", "b"]);
+        assert!(SettlementService::resolve_shares(&[], 100).is_err());
+        assert!(SettlementService::resolve_shares(&weights(&[("a", 0), ("b", 0)]), 100).is_err());
+        assert!(SettlementService::resolve_shares(&weights(&[("a", 1), ("b", 0)]), 100).is_err());
+        assert!(SettlementService::resolve_shares(&weights(&[("a", 1), ("unknown", 1)]), 100).is_err());
+
+        let mixed = vec![
+            PayoutShare { agent_id: "a".to_string(), share: ShareSpec::Amount(50) },
+            PayoutShare { agent_id: "b".to_string(), share: ShareSpec::Weight(1) },
+        ];
+        assert!(SettlementService::resolve_shares(&mixed, 100).is_err());
+    }
+}
@@ -1,6 +1,10 @@
 use crate::domain::*;
-use crate::services::{with_state, with_state_mut};
-use ic_cdk::api::time;
+use crate::domain::calendar;
+use crate::services::{with_state, with_state_mut, BillingService, EconState, JournalService, PaymentService};
+use crate::services::payment::PaymentRequest;
+use crate::infra::runtime::time;
+use sha2::{Sha256, Digest};
+use base64::{Engine as _, engine::general_purpose};
 use serde::{Deserialize, Serialize};
 use candid::CandidType;
 use std::collections::HashMap;
@@ -8,12 +12,21 @@ use std::collections::HashMap;
 /// Subscription service for managing user subscriptions and quotas
 pub struct SubscriptionService;
 
+/// Tier new users are enrolled in automatically
+pub const DEFAULT_TIER: &str = "basic";
+
+/// Tier whose limits apply while a paid subscription is unpaid, expired or suspended
+pub const FALLBACK_TIER: &str = "free";
+
 // Use domain types instead of redefining them
 use crate::domain::{TierConfig, InferenceRate, UsageMetrics, PaymentStatus, QuotaValidation, QuotaRemaining};
 
 impl SubscriptionService {
-    /// Get predefined subscription tiers
-    pub fn get_tier_configs() -> HashMap<String, TierConfig> {
+    const DEFAULT_RESERVATION_TTL: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+    const MAX_RESERVATION_TTL: u64 = 60 * 60 * 1_000_000_000; // 1 hour
+
+    /// Tier catalog seeded on install and for state that predates the editable catalog
+    pub fn default_tier_catalog() -> HashMap<String, TierConfig> {
         let mut tiers = HashMap::new();
         
         tiers.insert("free".to_string(), TierConfig {
@@ -30,6 +43,11 @@ impl SubscriptionService {
                 "Standard inference priority".to_string(),
                 "Community support".to_string(),
             ],
+            monthly_inference_limit: Some(100),
+            overage: None,
+            version: Some(1),
+            updated_at: None,
+            retired_at: None,
         });
 
         tiers.insert("basic".to_string(), TierConfig {
@@ -46,6 +64,11 @@ impl SubscriptionService {
                 "Standard inference priority".to_string(),
                 "FREE for 1 month".to_string(),
             ],
+            monthly_inference_limit: Some(1_000),
+            overage: None,
+            version: Some(1),
+            updated_at: None,
+            retired_at: None,
         });
 
         tiers.insert("pro".to_string(), TierConfig {
@@ -62,6 +85,17 @@ impl SubscriptionService {
                 "Priority inference".to_string(),
                 "Advanced analytics".to_string(),
             ],
+            monthly_inference_limit: Some(10_000),
+            overage: Some(OveragePolicy {
+                price_per_1k_tokens_e8s: 100_000,
+                price_per_agent_creation_e8s: 10_000_000,
+                max_overage_tokens: 500_000,
+                max_overage_agent_creations: 25,
+                settlement: OverageSettlement::Accrue,
+            }),
+            version: Some(1),
+            updated_at: None,
+            retired_at: None,
         });
 
         tiers.insert("enterprise".to_string(), TierConfig {
@@ -80,52 +114,181 @@ impl SubscriptionService {
                 "Priority support".to_string(),
                 "Custom integrations".to_string(),
             ],
+            monthly_inference_limit: Some(100_000),
+            overage: Some(OveragePolicy {
+                price_per_1k_tokens_e8s: 80_000,
+                price_per_agent_creation_e8s: 5_000_000,
+                max_overage_tokens: 2_000_000,
+                max_overage_agent_creations: 100,
+                settlement: OverageSettlement::Accrue,
+            }),
+            version: Some(1),
+            updated_at: None,
+            retired_at: None,
         });
 
         tiers
     }
 
+    /// Live tier catalog, excluding retired tiers
+    pub fn get_tier_configs() -> HashMap<String, TierConfig> {
+        with_state(|state| {
+            state.tier_catalog.as_ref()
+                .map(|catalog| {
+                    catalog.iter()
+                        .filter(|(_, tier)| !tier.is_retired())
+                        .map(|(tier_id, tier)| (tier_id.clone(), tier.clone()))
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+    }
+
+    /// Current version of a tier offered to new subscribers
+    pub fn get_tier_config(tier_id: &str) -> Result<TierConfig, String> {
+        with_state(|state| state.tier_catalog.as_ref().and_then(|catalog| catalog.get(tier_id)).cloned())
+            .filter(|tier| !tier.is_retired())
+            .ok_or_else(|| "Invalid subscription tier".to_string())
+    }
+
+    /// Full catalog including retired tiers (admin only)
+    pub fn list_tier_catalog() -> Vec<(String, TierConfig)> {
+        let mut tiers: Vec<(String, TierConfig)> = with_state(|state| {
+            state.tier_catalog.as_ref()
+                .map(|catalog| catalog.iter().map(|(tier_id, tier)| (tier_id.clone(), tier.clone())).collect())
+                .unwrap_or_default()
+        });
+        tiers.sort_by(|a, b| a.0.cmp(&b.0));
+        tiers
+    }
+
+    /// Every version of a tier, oldest first
+    pub fn get_tier_versions(tier_id: &str) -> Vec<TierConfig> {
+        with_state(|state| {
+            let mut versions = state.tier_history.as_ref()
+                .and_then(|history| history.get(tier_id))
+                .cloned()
+                .unwrap_or_default();
+            if let Some(current) = state.tier_catalog.as_ref().and_then(|catalog| catalog.get(tier_id)) {
+                versions.push(current.clone());
+            }
+            versions
+        })
+    }
+
+    /// Add a tier to the catalog (admin only)
+    pub fn create_tier(tier_id: String, config: TierConfig) -> Result<TierConfig, String> {
+        if tier_id.trim().is_empty() {
+            return Err("Tier ID cannot be empty".to_string());
+        }
+        config.validate()?;
+
+        with_state_mut(|state| {
+            let catalog = state.tier_catalog.get_or_insert_with(HashMap::new);
+            if catalog.contains_key(&tier_id) {
+                return Err("Tier already exists".to_string());
+            }
+            // Retired tiers stay in the catalog, so an id is never reused and numbering starts fresh
+            let tier = TierConfig {
+                version: Some(1),
+                updated_at: Some(time()),
+                retired_at: None,
+                ..config
+            };
+            catalog.insert(tier_id, tier.clone());
+            Ok(tier)
+        })
+    }
+
+    /// Publish a new version of a tier; existing subscribers keep theirs until renewal (admin only)
+    pub fn update_tier(tier_id: String, config: TierConfig) -> Result<TierConfig, String> {
+        config.validate()?;
+
+        with_state_mut(|state| {
+            let current = state.tier_catalog.as_ref()
+                .and_then(|catalog| catalog.get(&tier_id))
+                .cloned()
+                .ok_or("Tier not found")?;
+            if current.is_retired() {
+                return Err("Tier is retired".to_string());
+            }
+
+            let tier = TierConfig {
+                version: Some(current.version() + 1),
+                updated_at: Some(time()),
+                retired_at: None,
+                ..config
+            };
+            state.tier_history.get_or_insert_with(HashMap::new)
+                .entry(tier_id.clone())
+                .or_default()
+                .push(current);
+            state.tier_catalog.get_or_insert_with(HashMap::new).insert(tier_id, tier.clone());
+            Ok(tier)
+        })
+    }
+
+    /// Stop offering a tier; current subscribers keep it until their period ends (admin only)
+    pub fn retire_tier(tier_id: String) -> Result<TierConfig, String> {
+        if tier_id == DEFAULT_TIER {
+            return Err("The default tier cannot be retired".to_string());
+        }
+
+        with_state_mut(|state| {
+            let tier = state.tier_catalog.as_mut()
+                .and_then(|catalog| catalog.get_mut(&tier_id))
+                .ok_or("Tier not found")?;
+            if tier.is_retired() {
+                return Err("Tier is already retired".to_string());
+            }
+            let now = time();
+            tier.retired_at = Some(now);
+            tier.updated_at = Some(now);
+            Ok(tier.clone())
+        })
+    }
+
     /// Create a new subscription for a user
     pub async fn create_subscription(
         principal_id: String,
         tier_name: String,
         auto_renew: bool,
     ) -> Result<Subscription, String> {
-        let tier_configs = Self::get_tier_configs();
-        let tier_config = tier_configs.get(&tier_name)
-            .ok_or("Invalid subscription tier")?;
+        let tier_config = Self::get_tier_config(&tier_name)?;
 
         // Check if user already has an active subscription
         if Self::get_user_subscription(&principal_id).is_some() {
-            return Err("User already has an active subscription".to_string());
+            return Err("User already has an active subscription; use change_subscription_tier".to_string());
         }
 
-        // For free tier and basic tier, auto-renew is always true and payment status is always Active (both are free)
-        let actual_auto_renew = if tier_name == "free" || tier_name == "basic" { true } else { auto_renew };
-        let payment_status = if tier_name == "free" || tier_name == "basic" {
+        // Free tiers always auto-renew and need no payment
+        let is_free = tier_config.monthly_fee_usd == 0;
+        let actual_auto_renew = if is_free { true } else { auto_renew };
+        let payment_status = if is_free {
             PaymentStatus::Active
         } else {
             PaymentStatus::Pending
         };
 
         let now = time();
-        let expires_at = now + (30 * 24 * 60 * 60 * 1_000_000_000); // 30 days in nanoseconds
+        let expires_at = calendar::add_months(now, 1);
 
         let subscription = Subscription {
             principal_id: principal_id.clone(),
-            tier: tier_config.clone(),
+            tier_id: Some(tier_name),
+            tier: tier_config,
             started_at: now,
             expires_at,
             auto_renew: actual_auto_renew,
-            current_usage: UsageMetrics {
-                agents_created_this_month: 0,
-                tokens_used_this_month: 0,
-                inferences_this_month: 0,
-                last_reset_date: now,
-            },
+            current_usage: UsageMetrics::starting(now),
             payment_status,
             created_at: now,
             updated_at: now,
+            pending_change: None,
+            billing: None,
+            active_agents: None,
+            billing_anchor: Some(now),
+            overage_due_e8s: None,
         };
 
         // Store subscription
@@ -162,7 +325,141 @@ impl SubscriptionService {
         }
 
         // Create free Basic subscription for new user (this is now the default)
-        Self::create_subscription(principal_id, "basic".to_string(), true).await
+        Self::create_subscription(principal_id, DEFAULT_TIER.to_string(), true).await
+    }
+
+    /// Price a move to `new_tier` for the rest of the current period
+    pub fn quote_tier_change(principal_id: &str, new_tier: &str) -> Result<TierChange, String> {
+        let subscription = Self::get_user_subscription(principal_id).ok_or("No subscription found")?;
+        let from_tier_id = subscription.tier_id.clone().ok_or("Subscription is not linked to a catalog tier")?;
+        if from_tier_id == new_tier {
+            return Err("Already subscribed to this tier".to_string());
+        }
+        let target = Self::get_tier_config(new_tier)?;
+
+        let now = time();
+        if now >= subscription.expires_at {
+            return Err("Subscription period has ended; renew before changing tiers".to_string());
+        }
+        if !matches!(subscription.payment_status, PaymentStatus::Active) {
+            return Err("The current period must be paid before changing tiers".to_string());
+        }
+        let remaining_ns = subscription.expires_at - now;
+        let period_ns = subscription.expires_at.saturating_sub(subscription.started_at).max(remaining_ns);
+
+        let current_fee = subscription.tier.monthly_fee_usd;
+        let (direction, prorated_amount_e8s, effective_at) = if target.monthly_fee_usd >= current_fee {
+            // Charge the fee difference for the unused part of the period
+            let difference = PaymentService::usd_to_icp_e8s(target.monthly_fee_usd - current_fee)?;
+            let prorated = Money::from_e8s(difference).mul_div(remaining_ns, period_ns)?;
+            (TierChangeDirection::Upgrade, prorated.e8s(), now)
+        } else {
+            // The current period is already paid for, so the cheaper tier starts at renewal
+            (TierChangeDirection::Downgrade, 0, subscription.expires_at)
+        };
+
+        Ok(TierChange {
+            from_tier_id,
+            to_tier_id: new_tier.to_string(),
+            direction,
+            prorated_amount_e8s,
+            remaining_ns,
+            period_ns,
+            requested_at: now,
+            effective_at,
+        })
+    }
+
+    /// Move to another tier. Upgrades return a payment request for the prorated charge and apply once
+    /// it is paid; downgrades are scheduled for period end. Requesting the current tier cancels a
+    /// scheduled downgrade.
+    pub async fn change_subscription_tier(principal_id: String, new_tier: String) -> Result<TierChangeOutcome, String> {
+        let subscription = Self::get_user_subscription(&principal_id).ok_or("No subscription found")?;
+        if subscription.tier_id.as_deref() == Some(new_tier.as_str()) {
+            let change = subscription.pending_change.ok_or("Already subscribed to this tier")?;
+            with_state_mut(|state| {
+                if let Some(subscription) = state.subscriptions.get_mut(&principal_id) {
+                    subscription.pending_change = None;
+                    subscription.updated_at = time();
+                }
+            });
+            return Ok(TierChangeOutcome { change, payment_request: None, applied: false });
+        }
+
+        let change = Self::quote_tier_change(&principal_id, &new_tier)?;
+        match change.direction {
+            TierChangeDirection::Upgrade if change.prorated_amount_e8s > 0 => {
+                let payment_request = PaymentService::create_tier_change_request(principal_id, &change).await?;
+                Ok(TierChangeOutcome { change, payment_request: Some(payment_request), applied: false })
+            }
+            TierChangeDirection::Upgrade => {
+                // Nothing to pay (same price, or too little of the period left to charge for)
+                Self::apply_tier_upgrade(&principal_id, &change)?;
+                Ok(TierChangeOutcome { change, payment_request: None, applied: true })
+            }
+            TierChangeDirection::Downgrade => {
+                with_state_mut(|state| {
+                    if let Some(subscription) = state.subscriptions.get_mut(&principal_id) {
+                        subscription.pending_change = Some(change.clone());
+                        subscription.updated_at = time();
+                    }
+                });
+                Ok(TierChangeOutcome { change, payment_request: None, applied: false })
+            }
+        }
+    }
+
+    /// Switch a paid-up upgrade onto the subscription, keeping the current period and its payment status
+    pub fn apply_tier_upgrade(principal_id: &str, change: &TierChange) -> Result<Subscription, String> {
+        let tier = Self::get_tier_config(&change.to_tier_id)?;
+        with_state_mut(|state| {
+            let subscription = state.subscriptions.get_mut(principal_id).ok_or("No subscription found")?;
+            Self::check_tier_upgrade(subscription, change)?;
+            subscription.tier_id = Some(change.to_tier_id.clone());
+            subscription.tier = tier;
+            // An upgrade supersedes any scheduled downgrade
+            subscription.pending_change = None;
+            subscription.updated_at = time();
+            Ok(subscription.clone())
+        })
+    }
+
+    /// Check that a quoted upgrade still applies to the principal's subscription
+    pub fn validate_tier_upgrade(principal_id: &str, change: &TierChange) -> Result<(), String> {
+        let subscription = Self::get_user_subscription(principal_id).ok_or("No subscription found")?;
+        Self::check_tier_upgrade(&subscription, change)
+    }
+
+    /// An upgrade is priced for one paid period of one tier; it lapses when either changes
+    fn check_tier_upgrade(subscription: &Subscription, change: &TierChange) -> Result<(), String> {
+        if subscription.tier_id.as_deref() != Some(change.from_tier_id.as_str()) {
+            return Err("Subscription tier changed since the upgrade was quoted".to_string());
+        }
+        if subscription.expires_at != change.requested_at.saturating_add(change.remaining_ns) {
+            return Err("Subscription period changed since the upgrade was quoted".to_string());
+        }
+        if !matches!(subscription.payment_status, PaymentStatus::Active) {
+            return Err("The current period must be paid before upgrading".to_string());
+        }
+        Ok(())
+    }
+
+    /// Apply a scheduled downgrade once its period has ended
+    fn apply_due_tier_change(subscription: &mut Subscription, catalog: Option<&HashMap<String, TierConfig>>) {
+        let now = time();
+        let due = subscription.pending_change.as_ref().filter(|change| change.effective_at <= now).cloned();
+        if let Some(change) = due {
+            subscription.pending_change = None;
+            let target = catalog
+                .and_then(|catalog| catalog.get(&change.to_tier_id))
+                .filter(|tier| !tier.is_retired());
+            // A tier retired in the meantime leaves the subscriber where they are
+            if let Some(tier) = target {
+                subscription.tier_id = Some(change.to_tier_id);
+                subscription.tier = tier.clone();
+                subscription.updated_at = now;
+            }
+        }
     }
 
     /// Update subscription payment status
@@ -179,44 +476,40 @@ impl SubscriptionService {
         Ok(())
     }
 
-    /// Validate quota for agent creation (SIMPLIFIED - no payment checks for free tiers)
+    /// Validate quota for agent creation against the effective tier
     pub async fn validate_quota(principal_id: &str) -> Result<QuotaValidation, String> {
-        // Get or create free Basic subscription automatically
-        let subscription = Self::get_or_create_free_basic_subscription(principal_id.to_string()).await?;
+        let mut subscription = Self::load_for_quota(principal_id).await?;
+        let (_, tier, fallback_reason) = Self::effective_tier(&subscription);
+        let reserved = Self::reserved_tokens(principal_id);
 
-        // Reset monthly usage if needed
-        let mut updated_subscription = subscription.clone();
-        Self::reset_monthly_usage_if_needed(&mut updated_subscription);
-
-        // Check agent creation quota (no payment status checks for free Basic tier)
-        if updated_subscription.current_usage.agents_created_this_month >= updated_subscription.tier.monthly_agent_creations {
+        let denial = if Self::agent_creation_allowance(&tier, &subscription) == 0 {
+            Some("Monthly quota reached - upgrade for more")
+        } else if subscription.active_agent_count() >= tier.max_agents {
+            Some("Concurrent agent limit reached - stop an agent or upgrade")
+        } else {
+            None
+        };
+        if let Some(denial) = denial {
             return Ok(QuotaValidation {
                 allowed: false,
-                reason: Some("Monthly quota reached - upgrade for more".to_string()),
-                remaining_quota: Some(QuotaRemaining {
-                    agents_remaining: 0,
-                    tokens_remaining: updated_subscription.tier.token_limit.saturating_sub(updated_subscription.current_usage.tokens_used_this_month),
-                    inferences_remaining: 0,
-                }),
+                reason: Some(Self::with_fallback_reason(denial, &fallback_reason)),
+                remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+                reservation: None,
             });
         }
 
         // Update usage and store
-        updated_subscription.current_usage.agents_created_this_month += 1;
-        updated_subscription.updated_at = time();
+        let overage_charged = Self::record_usage(&mut subscription, &tier, 0, 1)?;
 
         with_state_mut(|state| {
-            state.subscriptions.insert(principal_id.to_string(), updated_subscription.clone());
+            state.subscriptions.insert(principal_id.to_string(), subscription.clone());
         });
 
         Ok(QuotaValidation {
             allowed: true,
-            reason: None,
-            remaining_quota: Some(QuotaRemaining {
-                agents_remaining: updated_subscription.tier.monthly_agent_creations.saturating_sub(updated_subscription.current_usage.agents_created_this_month),
-                tokens_remaining: updated_subscription.tier.token_limit.saturating_sub(updated_subscription.current_usage.tokens_used_this_month),
-                inferences_remaining: 0,
-            }),
+            reason: Self::overage_reason(overage_charged, fallback_reason),
+            remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+            reservation: None,
         })
     }
 
@@ -225,52 +518,466 @@ impl SubscriptionService {
         Self::validate_quota(principal_id).await
     }
 
-    /// Validate quota for token usage (SIMPLIFIED - no payment checks for free tiers)
+    /// Validate and record token usage in one step, against the effective tier. Callers that only
+    /// learn the real count after inference should reserve and commit instead.
     pub async fn validate_token_usage_quota(
         principal_id: &str,
         tokens_requested: u64,
     ) -> Result<QuotaValidation, String> {
-        // Get or create free Basic subscription automatically
-        let subscription = Self::get_or_create_free_basic_subscription(principal_id.to_string()).await?;
+        let mut subscription = Self::load_for_quota(principal_id).await?;
+        let (_, tier, fallback_reason) = Self::effective_tier(&subscription);
+        let reserved = Self::reserved_tokens(principal_id);
 
-        // Reset monthly usage if needed
-        let mut updated_subscription = subscription.clone();
-        Self::reset_monthly_usage_if_needed(&mut updated_subscription);
+        if tokens_requested > Self::token_allowance(&tier, &subscription, reserved) {
+            return Ok(QuotaValidation {
+                allowed: false,
+                reason: Some(Self::with_fallback_reason("Insufficient token quota", &fallback_reason)),
+                remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+                reservation: None,
+            });
+        }
 
-        // Check token quota (no payment status checks for free Basic tier)
-        let remaining_tokens = updated_subscription.tier.token_limit.saturating_sub(updated_subscription.current_usage.tokens_used_this_month);
+        // Update usage and store
+        let overage_charged = Self::record_usage(&mut subscription, &tier, tokens_requested, 0)?;
+
+        with_state_mut(|state| {
+            state.subscriptions.insert(principal_id.to_string(), subscription.clone());
+        });
+
+        Ok(QuotaValidation {
+            allowed: true,
+            reason: Self::overage_reason(overage_charged, fallback_reason),
+            remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+            reservation: None,
+        })
+    }
 
-        if tokens_requested > remaining_tokens {
+    /// Count one inference against the monthly limit, refusing it once the limit is reached
+    pub async fn record_inference(principal_id: &str) -> Result<QuotaValidation, String> {
+        let mut subscription = Self::load_for_quota(principal_id).await?;
+        let (_, tier, fallback_reason) = Self::effective_tier(&subscription);
+        let reserved = Self::reserved_tokens(principal_id);
+
+        if Self::remaining(&tier, &subscription, reserved).inferences_remaining == 0 {
             return Ok(QuotaValidation {
                 allowed: false,
-                reason: Some("Insufficient token quota".to_string()),
-                remaining_quota: Some(QuotaRemaining {
-                    agents_remaining: updated_subscription.tier.monthly_agent_creations.saturating_sub(updated_subscription.current_usage.agents_created_this_month),
-                    tokens_remaining: remaining_tokens,
-                    inferences_remaining: 0,
-                }),
+                reason: Some(Self::with_fallback_reason("Monthly inference limit reached", &fallback_reason)),
+                remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+                reservation: None,
             });
         }
 
-        // Update usage and store
-        updated_subscription.current_usage.tokens_used_this_month += tokens_requested;
-        updated_subscription.updated_at = time();
+        subscription.current_usage.inferences_this_month = subscription.current_usage.inferences_this_month.saturating_add(1);
+        subscription.updated_at = time();
 
         with_state_mut(|state| {
-            state.subscriptions.insert(principal_id.to_string(), updated_subscription.clone());
+            state.subscriptions.insert(principal_id.to_string(), subscription.clone());
         });
 
         Ok(QuotaValidation {
             allowed: true,
-            reason: None,
-            remaining_quota: Some(QuotaRemaining {
-                agents_remaining: updated_subscription.tier.monthly_agent_creations.saturating_sub(updated_subscription.current_usage.agents_created_this_month),
-                tokens_remaining: updated_subscription.tier.token_limit.saturating_sub(updated_subscription.current_usage.tokens_used_this_month),
-                inferences_remaining: 0,
-            }),
+            reason: fallback_reason,
+            remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+            reservation: None,
+        })
+    }
+
+    /// Mark an agent as running, refusing it above the tier's `max_agents`. Reporting an agent
+    /// that is already running is a no-op.
+    pub async fn agent_started(principal_id: &str, agent_id: String) -> Result<QuotaValidation, String> {
+        if agent_id.is_empty() {
+            return Err("Agent ID cannot be empty".to_string());
+        }
+        let mut subscription = Self::load_for_quota(principal_id).await?;
+        let (_, tier, fallback_reason) = Self::effective_tier(&subscription);
+        let reserved = Self::reserved_tokens(principal_id);
+
+        let active = subscription.active_agents.get_or_insert_with(Vec::new);
+        if !active.contains(&agent_id) {
+            if active.len() as u32 >= tier.max_agents {
+                return Ok(QuotaValidation {
+                    allowed: false,
+                    reason: Some(Self::with_fallback_reason("Concurrent agent limit reached - stop an agent or upgrade", &fallback_reason)),
+                    remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+                    reservation: None,
+                });
+            }
+            active.push(agent_id);
+        }
+        subscription.updated_at = time();
+
+        with_state_mut(|state| {
+            state.subscriptions.insert(principal_id.to_string(), subscription.clone());
+        });
+
+        Ok(QuotaValidation {
+            allowed: true,
+            reason: fallback_reason,
+            remaining_quota: Some(Self::remaining(&tier, &subscription, reserved)),
+            reservation: None,
+        })
+    }
+
+    /// Mark an agent as stopped, returning how many remain running
+    pub fn agent_stopped(principal_id: &str, agent_id: &str) -> Result<u32, String> {
+        with_state_mut(|state| {
+            let subscription = state.subscriptions.get_mut(principal_id).ok_or("No subscription found")?;
+            let active = subscription.active_agents.get_or_insert_with(Vec::new);
+            let before = active.len();
+            active.retain(|id| id != agent_id);
+            if active.len() == before {
+                return Err("Agent is not running".to_string());
+            }
+            subscription.updated_at = time();
+            Ok(subscription.active_agent_count())
+        })
+    }
+
+    /// Hold `tokens` against the quota until committed, released or expired
+    pub async fn reserve_tokens(
+        principal_id: &str,
+        tokens: u64,
+        ttl: Option<u64>,
+        reserved_by: String,
+    ) -> Result<QuotaValidation, String> {
+        if tokens == 0 {
+            return Err("Reserved tokens must be greater than zero".to_string());
+        }
+        let ttl = ttl.unwrap_or(Self::DEFAULT_RESERVATION_TTL);
+        if ttl == 0 || ttl > Self::MAX_RESERVATION_TTL {
+            return Err(format!("Reservation TTL must be between 1 and {} ns", Self::MAX_RESERVATION_TTL));
+        }
+
+        let subscription = Self::load_for_quota(principal_id).await?;
+        let (_, tier, fallback_reason) = Self::effective_tier(&subscription);
+        let reserved = Self::reserved_tokens(principal_id);
+        let remaining = Self::remaining(&tier, &subscription, reserved);
+
+        if tokens > Self::token_allowance(&tier, &subscription, reserved) {
+            return Ok(QuotaValidation {
+                allowed: false,
+                reason: Some(Self::with_fallback_reason("Insufficient token quota", &fallback_reason)),
+                remaining_quota: Some(remaining),
+                reservation: None,
+            });
+        }
+
+        let now = time();
+        let reservation = with_state_mut(|state| {
+            // Keep the usage reset from loading so reservations count against the current month
+            state.subscriptions.insert(principal_id.to_string(), subscription.clone());
+
+            let reservations = state.quota_reservations.get_or_insert_with(HashMap::new);
+            let mut nonce = reservations.len() as u64;
+            let reservation_id = loop {
+                let candidate = Self::generate_reservation_id(principal_id, now, nonce);
+                if !reservations.contains_key(&candidate) {
+                    break candidate;
+                }
+                nonce += 1;
+            };
+
+            let reservation = QuotaReservation {
+                reservation_id: reservation_id.clone(),
+                principal_id: principal_id.to_string(),
+                reserved_by,
+                tokens,
+                created_at: now,
+                expires_at: now.saturating_add(ttl),
+            };
+            reservations.insert(reservation_id, reservation.clone());
+            reservation
+        });
+
+        Ok(QuotaValidation {
+            allowed: true,
+            reason: fallback_reason,
+            remaining_quota: Some(Self::remaining(&tier, &subscription, reserved + tokens)),
+            reservation: Some(reservation),
+        })
+    }
+
+    /// Close a reservation and record the tokens actually used, which may exceed the reservation.
+    /// Usage past the quota is billed as overage; usage past the overage cap is rejected and the
+    /// reservation stays open.
+    pub fn commit_reservation(reservation_id: &str, tokens_used: u64, caller: &str) -> Result<UsageMetrics, String> {
+        let reservation = Self::take_reservation(reservation_id, caller)?;
+        let mut subscription = Self::get_user_subscription(&reservation.principal_id).ok_or("No subscription found")?;
+        let (_, tier, _) = Self::effective_tier(&subscription);
+
+        // The taken reservation no longer counts as reserved, so its tokens are back in the allowance
+        let allowance = Self::token_allowance(&tier, &subscription, Self::reserved_tokens(&reservation.principal_id));
+        if tokens_used > allowance {
+            let message = format!("Usage of {} tokens exceeds the remaining allowance of {} including overage", tokens_used, allowance);
+            with_state_mut(|state| {
+                state.quota_reservations
+                    .get_or_insert_with(HashMap::new)
+                    .insert(reservation.reservation_id.clone(), reservation);
+            });
+            return Err(message);
+        }
+        Self::record_usage(&mut subscription, &tier, tokens_used, 0)?;
+
+        with_state_mut(|state| {
+            state.subscriptions.insert(reservation.principal_id.clone(), subscription.clone());
+        });
+        Ok(subscription.current_usage)
+    }
+
+    /// Return a reservation's tokens to the quota without recording usage
+    pub fn release_reservation(reservation_id: &str, caller: &str) -> Result<(), String> {
+        Self::take_reservation(reservation_id, caller).map(|_| ())
+    }
+
+    /// Drop up to `limit` reservations past `expires_at`, returning how many were released
+    pub fn release_expired_reservations(limit: u32) -> u32 {
+        let now = time();
+        with_state_mut(|state| {
+            let Some(reservations) = state.quota_reservations.as_mut() else {
+                return 0;
+            };
+            let expired: Vec<String> = reservations.values()
+                .filter(|reservation| reservation.expires_at <= now)
+                .take(limit as usize)
+                .map(|reservation| reservation.reservation_id.clone())
+                .collect();
+            for reservation_id in &expired {
+                reservations.remove(reservation_id);
+            }
+            expired.len() as u32
+        })
+    }
+
+    /// Open reservations for a principal, oldest first
+    pub fn list_reservations(principal_id: &str) -> Vec<QuotaReservation> {
+        let now = time();
+        let mut reservations: Vec<QuotaReservation> = with_state(|state| {
+            state.quota_reservations.as_ref()
+                .map(|reservations| {
+                    reservations.values()
+                        .filter(|reservation| reservation.principal_id == principal_id && reservation.expires_at > now)
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+        reservations.sort_by_key(|reservation| reservation.created_at);
+        reservations
+    }
+
+    /// Tokens held by a principal's unexpired reservations
+    fn reserved_tokens(principal_id: &str) -> u64 {
+        let now = time();
+        with_state(|state| {
+            state.quota_reservations.as_ref()
+                .map(|reservations| {
+                    reservations.values()
+                        .filter(|reservation| reservation.principal_id == principal_id && reservation.expires_at > now)
+                        .fold(0u64, |total, reservation| total.saturating_add(reservation.tokens))
+                })
+                .unwrap_or(0)
+        })
+    }
+
+    /// Remove an unexpired reservation on behalf of its reserver, its subscriber or an admin
+    fn take_reservation(reservation_id: &str, caller: &str) -> Result<QuotaReservation, String> {
+        let now = time();
+        with_state_mut(|state| {
+            let is_admin = state.admins.iter().any(|admin| admin == caller);
+            let reservations = state.quota_reservations.get_or_insert_with(HashMap::new);
+            let reservation = reservations.get(reservation_id).ok_or("Reservation not found")?;
+            if reservation.reserved_by != caller && reservation.principal_id != caller && !is_admin {
+                return Err("Not authorized for this reservation".to_string());
+            }
+            let reservation = reservations.remove(reservation_id).ok_or("Reservation not found")?;
+            if reservation.expires_at <= now {
+                return Err("Reservation expired".to_string());
+            }
+            Ok(reservation)
+        })
+    }
+
+    fn generate_reservation_id(principal_id: &str, now: u64, nonce: u64) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(principal_id.as_bytes());
+        hasher.update(now.to_be_bytes());
+        hasher.update(nonce.to_be_bytes());
+        let hash = hasher.finalize();
+        format!("rsv_{}", general_purpose::STANDARD.encode(&hash[..8]))
+    }
+
+    /// Subscription with due downgrades and monthly resets applied, enrolling new users in the default tier
+    async fn load_for_quota(principal_id: &str) -> Result<Subscription, String> {
+        Self::get_or_create_free_basic_subscription(principal_id.to_string()).await?;
+        // The timer normally rolls usage; catching up here keeps quota checks exact at the boundary
+        with_state_mut(|state| Self::roll_usage_in_state(state, principal_id, time()));
+        let mut subscription = Self::get_user_subscription(principal_id).ok_or("No subscription found")?;
+        with_state(|state| Self::apply_due_tier_change(&mut subscription, state.tier_catalog.as_ref()));
+        Ok(subscription)
+    }
+
+    /// Tier whose limits apply right now: the subscribed tier while it is paid for (or renewing
+    /// within grace), the fallback tier otherwise, with the reason for falling back
+    pub fn effective_tier(subscription: &Subscription) -> (Option<String>, TierConfig, Option<String>) {
+        let now = time();
+        let grace_ends_at = subscription.billing.as_ref()
+            .and_then(|billing| billing.grace_ends_at)
+            .unwrap_or_else(|| subscription.expires_at.saturating_add(with_state(|state| state.billing_policy()).grace_period));
+
+        let fallback_reason = match subscription.payment_status {
+            PaymentStatus::Pending => Some("Subscription payment is pending"),
+            PaymentStatus::Failed => Some("Subscription payment failed"),
+            PaymentStatus::Suspended => Some("Subscription suspended for non-payment"),
+            _ if now < subscription.expires_at => None,
+            // Auto-renewing subscriptions keep their tier while billing retries
+            PaymentStatus::Active | PaymentStatus::GracePeriod if subscription.auto_renew && now < grace_ends_at => None,
+            _ => Some("Subscription expired"),
+        };
+
+        match fallback_reason {
+            None => (subscription.tier_id.clone(), subscription.tier.clone(), None),
+            Some(reason) => {
+                let fallback = Self::fallback_tier();
+                let reason = format!("{}; {} tier limits apply", reason, fallback.name);
+                (Some(FALLBACK_TIER.to_string()), fallback, Some(reason))
+            }
+        }
+    }
+
+    /// Current limits, usage and remaining quota for a principal
+    pub fn get_entitlements(principal_id: &str) -> Option<Entitlements> {
+        let mut subscription = Self::get_user_subscription(principal_id)?;
+        with_state(|state| Self::apply_due_tier_change(&mut subscription, state.tier_catalog.as_ref()));
+        Self::roll_usage(&mut subscription, time());
+        let (effective_tier_id, tier, fallback_reason) = Self::effective_tier(&subscription);
+        let tokens_reserved = Self::reserved_tokens(principal_id);
+
+        let remaining = Self::remaining(&tier, &subscription, tokens_reserved);
+        let active_agents = subscription.active_agent_count();
+
+        Some(Entitlements {
+            principal_id: subscription.principal_id,
+            subscribed_tier_id: subscription.tier_id,
+            effective_tier_id,
+            remaining,
+            active_agents,
+            tokens_reserved,
+            overage_due_e8s: subscription.overage_due_e8s.unwrap_or(0),
+            tier,
+            fallback_reason,
+            payment_status: subscription.payment_status,
+            expires_at: subscription.expires_at,
+            usage: subscription.current_usage,
+        })
+    }
+
+    /// The fallback tier, even if retired; without one, nothing is allowed
+    fn fallback_tier() -> TierConfig {
+        with_state(|state| state.tier_catalog.as_ref().and_then(|catalog| catalog.get(FALLBACK_TIER)).cloned())
+            .unwrap_or_else(|| TierConfig {
+                name: "Restricted".to_string(),
+                monthly_fee_usd: 0,
+                max_agents: 0,
+                monthly_agent_creations: 0,
+                token_limit: 0,
+                inference_rate: InferenceRate::Standard,
+                features: Vec::new(),
+                monthly_inference_limit: Some(0),
+                overage: None,
+                version: None,
+                updated_at: None,
+                retired_at: None,
+            })
+    }
+
+    fn remaining(tier: &TierConfig, subscription: &Subscription, reserved_tokens: u64) -> QuotaRemaining {
+        let usage = &subscription.current_usage;
+        QuotaRemaining {
+            agents_remaining: tier.monthly_agent_creations.saturating_sub(usage.agents_created_this_month),
+            tokens_remaining: tier.token_limit.saturating_sub(usage.tokens_used_this_month).saturating_sub(reserved_tokens),
+            inferences_remaining: tier.monthly_inference_limit
+                .map_or(u32::MAX, |limit| limit.saturating_sub(usage.inferences_this_month)),
+            concurrent_agents_remaining: tier.max_agents.saturating_sub(subscription.active_agent_count()),
+        }
+    }
+
+    /// Tokens that may still be used this period, counting the tier's overage headroom
+    fn token_allowance(tier: &TierConfig, subscription: &Subscription, reserved_tokens: u64) -> u64 {
+        let overage_cap = tier.overage.as_ref().map_or(0, |overage| overage.max_overage_tokens);
+        tier.token_limit.saturating_add(overage_cap)
+            .saturating_sub(subscription.current_usage.tokens_used_this_month)
+            .saturating_sub(reserved_tokens)
+    }
+
+    /// Agent creations still allowed this period, counting the tier's overage headroom
+    fn agent_creation_allowance(tier: &TierConfig, subscription: &Subscription) -> u32 {
+        let overage_cap = tier.overage.as_ref().map_or(0, |overage| overage.max_overage_agent_creations);
+        tier.monthly_agent_creations.saturating_add(overage_cap)
+            .saturating_sub(subscription.current_usage.agents_created_this_month)
+    }
+
+    /// Add usage to the current period and bill whatever of it falls past the quota, returning
+    /// the overage charged
+    fn record_usage(subscription: &mut Subscription, tier: &TierConfig, tokens: u64, agent_creations: u32) -> Result<u64, String> {
+        let now = time();
+        let usage = &mut subscription.current_usage;
+        let tokens_over_before = usage.tokens_used_this_month.saturating_sub(tier.token_limit);
+        let creations_over_before = usage.agents_created_this_month.saturating_sub(tier.monthly_agent_creations);
+        usage.tokens_used_this_month = usage.tokens_used_this_month.saturating_add(tokens);
+        usage.agents_created_this_month = usage.agents_created_this_month.saturating_add(agent_creations);
+        subscription.updated_at = now;
+
+        let Some(overage) = &tier.overage else {
+            return Ok(0);
+        };
+        let tokens_over = usage.tokens_used_this_month.saturating_sub(tier.token_limit);
+        let creations_over = usage.agents_created_this_month.saturating_sub(tier.monthly_agent_creations);
+
+        // Tokens are priced on the running overage so per-call rounding never adds up
+        let token_price = Money::from_e8s(overage.price_per_1k_tokens_e8s);
+        let token_charge = token_price.mul_div_ceil(tokens_over, 1_000)?
+            .checked_sub(token_price.mul_div_ceil(tokens_over_before, 1_000)?)?;
+        let creation_charge = Money::from_e8s(overage.price_per_agent_creation_e8s)
+            .checked_mul((creations_over - creations_over_before) as u64)?;
+        let charge = token_charge.checked_add(creation_charge)?.e8s();
+
+        usage.overage_tokens = Some(tokens_over);
+        usage.overage_agent_creations = Some(creations_over);
+        if charge == 0 {
+            return Ok(0);
+        }
+        usage.overage_charged_e8s = Some(usage.overage_charged_e8s.unwrap_or(0).saturating_add(charge));
+
+        let debited = overage.settlement == OverageSettlement::DebitBalance && with_state_mut(|state| {
+            let mut postings = JournalService::spend_postings(state, &subscription.principal_id, charge)?;
+            postings.push(Posting::credit(JournalAccount::ProtocolTreasury, charge));
+            let reference = format!("overage:{}:{}", subscription.principal_id, now);
+            JournalService::post(state, JournalEntryKind::OverageCharge, &reference, postings)
+        }).is_ok();
+        if !debited {
+            subscription.overage_due_e8s = Some(subscription.overage_due_e8s.unwrap_or(0).saturating_add(charge));
+        }
+        Ok(charge)
+    }
+
+    fn overage_reason(overage_charged: u64, fallback_reason: Option<String>) -> Option<String> {
+        if overage_charged == 0 {
+            return fallback_reason;
+        }
+        let overage = format!("Usage past quota billed as overage: {} e8s", overage_charged);
+        Some(match fallback_reason {
+            Some(fallback) => format!("{} ({})", overage, fallback),
+            None => overage,
         })
     }
 
+    fn with_fallback_reason(reason: &str, fallback_reason: &Option<String>) -> String {
+        match fallback_reason {
+            Some(fallback) => format!("{} ({})", reason, fallback),
+            None => reason.to_string(),
+        }
+    }
+
     /// Get user usage metrics
     pub fn get_user_usage(principal_id: &str) -> Option<UsageMetrics> {
         Self::get_user_subscription(principal_id)
@@ -295,41 +1002,143 @@ impl SubscriptionService {
         Ok(())
     }
 
-    /// Renew subscription
+    /// Renew subscription now, charging the price of the tier the next period runs on
     pub async fn renew_subscription(principal_id: String) -> Result<(), String> {
+        BillingService::renew(&principal_id).await.map(|_| ())
+    }
+
+    /// Tier the next period runs on: any scheduled downgrade, at the tier's current catalog version.
+    /// A retired tier keeps the version the subscriber already has.
+    pub fn renewal_tier(subscription: &Subscription) -> (Option<String>, TierConfig) {
+        with_state(|state| {
+            let catalog = state.tier_catalog.as_ref();
+            let live = |tier_id: &String| catalog.and_then(|catalog| catalog.get(tier_id)).filter(|tier| !tier.is_retired()).cloned();
+
+            if let Some(change) = &subscription.pending_change {
+                if let Some(tier) = live(&change.to_tier_id) {
+                    return (Some(change.to_tier_id.clone()), tier);
+                }
+            }
+            let tier = subscription.tier_id.as_ref().and_then(live).unwrap_or_else(|| subscription.tier.clone());
+            (subscription.tier_id.clone(), tier)
+        })
+    }
+
+    /// Start the next paid period on `tier`
+    pub fn complete_renewal(
+        principal_id: &str,
+        tier_id: Option<String>,
+        tier: TierConfig,
+        overage_paid_e8s: u64,
+    ) -> Result<Subscription, String> {
         with_state_mut(|state| {
-            if let Some(subscription) = state.subscriptions.get_mut(&principal_id) {
-                let now = time();
-                subscription.expires_at = now + (30 * 24 * 60 * 60 * 1_000_000_000); // 30 days
-                subscription.payment_status = PaymentStatus::Active;
-                subscription.updated_at = now;
-                
-                // Reset monthly usage
-                subscription.current_usage = UsageMetrics {
-                    agents_created_this_month: 0,
-                    tokens_used_this_month: 0,
-                    inferences_this_month: 0,
-                    last_reset_date: now,
-                };
+            let policy = state.billing_policy();
+            let subscription = state.subscriptions.get_mut(principal_id).ok_or("No subscription found")?;
+            let now = time();
+
+            // Periods run back to back while billing keeps up; a lapsed or suspended subscription starts afresh
+            let lapsed = subscription.payment_status == PaymentStatus::Suspended
+                || now > subscription.expires_at.saturating_add(policy.grace_period);
+            let start = if lapsed {
+                subscription.billing_anchor = Some(now);
+                now
+            } else {
+                subscription.expires_at.max(subscription.started_at)
+            };
+
+            subscription.tier_id = tier_id;
+            subscription.tier = tier;
+            subscription.pending_change = None;
+            subscription.started_at = start;
+            subscription.expires_at = calendar::period_bounds(subscription.billing_anchor(), start).1;
+            subscription.payment_status = PaymentStatus::Active;
+            subscription.billing = None;
+            // Overage accrued while the charge was in flight waits for the next renewal
+            subscription.overage_due_e8s = subscription.overage_due_e8s.map(|due| due.saturating_sub(overage_paid_e8s));
+            subscription.updated_at = now;
+            let renewed = subscription.clone();
+
+            // A fresh anchor starts a new usage period; renewals charged ahead of expiry leave usage to the timer
+            Self::roll_usage_in_state(state, principal_id, now);
+            Ok(renewed)
+        })
+    }
+
+    /// Clear overage collected from a subscription that no longer renews
+    pub fn settle_overage(principal_id: &str, paid_e8s: u64) -> Result<Subscription, String> {
+        with_state_mut(|state| {
+            let subscription = state.subscriptions.get_mut(principal_id).ok_or("No subscription found")?;
+            let due = subscription.overage_due_e8s.unwrap_or(0).saturating_sub(paid_e8s);
+            subscription.overage_due_e8s = Some(due);
+            if due == 0 {
+                subscription.billing = None;
             }
-        });
-        Ok(())
+            subscription.updated_at = time();
+            Ok(subscription.clone())
+        })
     }
 
-    /// Reset monthly usage if a new month has started
-    fn reset_monthly_usage_if_needed(subscription: &mut Subscription) {
-        let now = time();
+    /// Archive usage and start a new usage period once the anchored period `now` falls in has moved on
+    fn roll_usage(subscription: &mut Subscription, now: u64) -> Option<UsagePeriod> {
+        let anchor = subscription.billing_anchor();
+        let (current_start, _) = calendar::period_bounds(anchor, now);
         let last_reset = subscription.current_usage.last_reset_date;
-        
-        // Check if we're in a new month (simple check: 30 days passed)
-        if now - last_reset > 30 * 24 * 60 * 60 * 1_000_000_000 {
-            subscription.current_usage = UsageMetrics {
-                agents_created_this_month: 0,
-                tokens_used_this_month: 0,
-                inferences_this_month: 0,
-                last_reset_date: now,
-            };
+        if last_reset >= current_start {
+            return None;
         }
+
+        // Usage is only tracked per period actually used, so idle periods in between are not archived
+        let (_, last_end) = calendar::period_bounds(anchor, last_reset);
+        let finished = std::mem::replace(&mut subscription.current_usage, UsageMetrics::starting(current_start));
+        Some(UsagePeriod {
+            period_start: last_reset,
+            period_end: last_end.min(current_start),
+            tier_id: subscription.tier_id.clone(),
+            usage: finished,
+        })
+    }
+
+    /// Roll a stored subscription's usage period, archiving the finished one
+    fn roll_usage_in_state(state: &mut EconState, principal_id: &str, now: u64) -> bool {
+        let Some(subscription) = state.subscriptions.get_mut(principal_id) else {
+            return false;
+        };
+        let Some(period) = Self::roll_usage(subscription, now) else {
+            return false;
+        };
+        subscription.updated_at = now;
+        state.usage_history.get_or_insert_with(HashMap::new)
+            .entry(principal_id.to_string())
+            .or_default()
+            .push(period);
+        true
+    }
+
+    /// Start new usage periods for up to `limit` subscriptions whose period has ended
+    pub fn roll_usage_periods(limit: u32) -> UsageRollBatch {
+        let now = time();
+        with_state_mut(|state| {
+            let mut due: Vec<String> = state.subscriptions.values()
+                .filter(|subscription| {
+                    subscription.current_usage.last_reset_date < calendar::period_bounds(subscription.billing_anchor(), now).0
+                })
+                .map(|subscription| subscription.principal_id.clone())
+                .collect();
+            let backlog = due.len().saturating_sub(limit as usize) as u32;
+            due.truncate(limit as usize);
+            let rolled = due.iter().filter(|principal_id| Self::roll_usage_in_state(state, principal_id, now)).count() as u32;
+            UsageRollBatch { rolled, backlog }
+        })
+    }
+
+    /// Archived usage for a principal, newest first
+    pub fn get_usage_history(principal_id: &str, limit: u32) -> Vec<UsagePeriod> {
+        with_state(|state| {
+            state.usage_history.as_ref()
+                .and_then(|history| history.get(principal_id))
+                .map(|periods| periods.iter().rev().take(limit as usize).cloned().collect())
+                .unwrap_or_default()
+        })
     }
 
     /// Get subscription statistics (admin only)
@@ -384,3 +1193,21 @@ pub struct SubscriptionStats {
     pub tier_distribution: HashMap<String, u32>,
     pub total_monthly_revenue_usd: u32,
 }
+
+/// Result of a tier change request
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TierChangeOutcome {
+    pub change: TierChange,
+    // Set for upgrades that must be paid before they apply
+    pub payment_request: Option<PaymentRequest>,
+    // Whether the subscription is already on the new tier
+    pub applied: bool,
+}
+
+/// Outcome of one bounded usage-period rollover pass
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsageRollBatch {
+    pub rolled: u32,
+    // Subscriptions still due a new usage period, left for a later pass
+    pub backlog: u32,
+}
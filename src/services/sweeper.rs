@@ -0,0 +1,75 @@
+use crate::domain::*;
+use crate::services::{with_state, with_state_mut, DisputeService, EscrowService, SubscriptionService};
+use crate::infra::runtime::time;
+use crate::infra::PeriodicTimer;
+use std::time::Duration;
+
+thread_local! {
+    static SWEEP_TIMER: PeriodicTimer = const { PeriodicTimer::new() };
+}
+
+/// Periodic job expiring escrows past `expires_at` so their funds return to the holders, and
+/// releasing held agent payouts once their dispute window closes
+pub struct SweeperService;
+
+impl SweeperService {
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+    const BATCH_SIZE: u32 = 100;
+
+    /// Arm the periodic sweep; called from `init` and `post_upgrade`
+    pub fn start() {
+        SWEEP_TIMER.with(|timer| timer.start(Self::SWEEP_INTERVAL, || {
+            Self::run(SweepTrigger::Timer);
+        }));
+    }
+
+    pub fn stop() {
+        SWEEP_TIMER.with(PeriodicTimer::stop);
+    }
+
+    pub fn is_running() -> bool {
+        SWEEP_TIMER.with(PeriodicTimer::is_running)
+    }
+
+    /// Expire one batch of escrows, release one batch of matured payouts and record the outcome. A timer run that leaves a backlog
+    /// schedules another batch right away instead of waiting for the next interval.
+    pub fn run(trigger: SweepTrigger) -> SweeperStatus {
+        let batch = EscrowService::cleanup_expired_escrows(Self::BATCH_SIZE);
+        // Stale quota reservations ride along; they need no ledger work
+        SubscriptionService::release_expired_reservations(Self::BATCH_SIZE);
+        let released_payouts = DisputeService::release_matured_payouts(Self::BATCH_SIZE);
+        let now = time();
+
+        let status = with_state_mut(|state| {
+            let previous = state.sweeper_status.as_ref();
+            let status = SweeperStatus {
+                last_run_at: now,
+                last_trigger: trigger.clone(),
+                last_expired: batch.expired,
+                last_failed: batch.failed,
+                backlog: batch.backlog,
+                total_expired: previous.map(|s| s.total_expired).unwrap_or(0) + batch.expired as u64,
+                runs: previous.map(|s| s.runs).unwrap_or(0) + 1,
+                last_released_payouts: Some(released_payouts),
+            };
+            state.sweeper_status = Some(status.clone());
+            if batch.expired > 0 || released_payouts > 0 {
+                state.metrics.last_activity = now;
+            }
+            status
+        });
+
+        // Only continue when the batch made progress, so escrows that keep failing cannot spin the timer
+        if trigger == SweepTrigger::Timer && batch.backlog > 0 && batch.expired > 0 {
+            ic_cdk_timers::set_timer(Duration::ZERO, || {
+                Self::run(SweepTrigger::Timer);
+            });
+        }
+
+        status
+    }
+
+    pub fn get_status() -> Option<SweeperStatus> {
+        with_state(|state| state.sweeper_status.clone())
+    }
+}
@@ -0,0 +1,150 @@
+use crate::domain::*;
+use crate::services::{with_state, with_state_mut, EconState, JournalService};
+use crate::services::payment::AccountIdentifier;
+use crate::infra::LedgerClient;
+use crate::infra::ledger::Account;
+use candid::{CandidType, Principal};
+use crate::infra::runtime::time;
+use serde::{Deserialize, Serialize};
+
+/// Treasury service: protocol fee distribution and treasury withdrawals
+pub struct TreasuryService;
+
+impl TreasuryService {
+    /// Ledger account receiving subscription payments; this canister's main account unless configured
+    pub fn treasury_account() -> Account {
+        with_state(|state| state.treasury_account.clone())
+            .unwrap_or_else(|| LedgerClient::canister_account(None))
+    }
+
+    pub fn get_treasury_account() -> TreasuryAccountInfo {
+        let account = Self::treasury_account();
+        let account_identifier = AccountIdentifier::from_account(&account)
+            .map(|id| id.to_hex())
+            .unwrap_or_default();
+        TreasuryAccountInfo { account, account_identifier }
+    }
+
+    pub fn set_treasury_account(owner: Principal, subaccount: Option<Vec<u8>>) -> Result<(), String> {
+        if owner == Principal::anonymous() {
+            return Err("Treasury owner cannot be anonymous".to_string());
+        }
+        if let Some(bytes) = subaccount.as_ref() {
+            AccountIdentifier::subaccount_from_slice(bytes)?;
+        }
+
+        with_state_mut(|state| {
+            state.treasury_account = Some(Account { owner, subaccount });
+            state.metrics.last_activity = time();
+        });
+
+        Ok(())
+    }
+
+    pub fn get_fee_sinks() -> Vec<FeeSink> {
+        with_state(|state| state.fee_sinks.clone().unwrap_or_default())
+    }
+
+    pub fn set_fee_sinks(sinks: Vec<FeeSink>) -> Result<(), String> {
+        let mut total_bps: u32 = 0;
+        for (index, sink) in sinks.iter().enumerate() {
+            if sink.name.is_empty() {
+                return Err("Fee sink name cannot be empty".to_string());
+            }
+            if sink.share_bps == 0 {
+                return Err(format!("Fee sink {} must have a non-zero share", sink.name));
+            }
+            if sinks[..index].iter().any(|other| other.name == sink.name) {
+                return Err(format!("Duplicate fee sink: {}", sink.name));
+            }
+            total_bps = total_bps.saturating_add(sink.share_bps);
+        }
+
+        if total_bps > BasisPoints::ONE_HUNDRED_PERCENT.value() {
+            return Err("Fee sink shares exceed 100%".to_string());
+        }
+
+        with_state_mut(|state| {
+            state.fee_sinks = Some(sinks);
+            state.metrics.last_activity = time();
+        });
+
+        Ok(())
+    }
+
+    /// Credit postings distributing `protocol_fee` across fee sinks, remainder to the treasury.
+    /// Sink shares round down, so rounding dust always lands in the treasury.
+    pub fn fee_postings(state: &EconState, protocol_fee: u64) -> Result<Vec<Posting>, String> {
+        let mut postings = Vec::new();
+        if protocol_fee == 0 {
+            return Ok(postings);
+        }
+
+        let fee = Money::from_e8s(protocol_fee);
+        let mut distributed = Money::ZERO;
+        if let Some(sinks) = state.fee_sinks.as_ref() {
+            for sink in sinks {
+                let share = fee.apply_bps(BasisPoints(sink.share_bps))?;
+                if share > Money::ZERO {
+                    postings.push(Posting::credit(JournalAccount::FeeSink(sink.name.clone()), share.e8s()));
+                    distributed = distributed.checked_add(share)?;
+                }
+            }
+        }
+
+        let remainder = fee.checked_sub(distributed)?;
+        if remainder > Money::ZERO {
+            postings.push(Posting::credit(JournalAccount::ProtocolTreasury, remainder.e8s()));
+        }
+
+        Ok(postings)
+    }
+
+    pub fn get_treasury_balance() -> TreasuryBalance {
+        let sink_names: Vec<String> = Self::get_fee_sinks().into_iter().map(|sink| sink.name).collect();
+
+        TreasuryBalance {
+            protocol_treasury: JournalService::account_balance(&JournalAccount::ProtocolTreasury),
+            fee_sinks: sink_names
+                .into_iter()
+                .map(|name| {
+                    let balance = JournalService::account_balance(&JournalAccount::FeeSink(name.clone()));
+                    (name, balance)
+                })
+                .collect(),
+        }
+    }
+
+    /// Move collected fees from the treasury (or a named fee sink) to a principal's available balance
+    pub fn withdraw(fee_sink: Option<String>, amount: u64, recipient: Principal) -> Result<u64, String> {
+        if recipient == Principal::anonymous() {
+            return Err("Recipient cannot be anonymous".to_string());
+        }
+        let recipient = recipient.to_text();
+
+        let source = match fee_sink {
+            Some(name) => JournalAccount::FeeSink(name),
+            None => JournalAccount::ProtocolTreasury,
+        };
+
+        with_state_mut(|state| {
+            let entry_id = JournalService::transfer(
+                state,
+                JournalEntryKind::TreasuryWithdrawal,
+                &recipient,
+                source,
+                JournalAccount::UserAvailable(recipient.clone()),
+                amount,
+            )?;
+            state.metrics.last_activity = time();
+            Ok(entry_id)
+        })
+    }
+}
+
+/// Treasury ledger account with its legacy ICP account identifier
+#[derive(Debug, Clone, Serialize, Deserialize, CandidType)]
+pub struct TreasuryAccountInfo {
+    pub account: Account,
+    pub account_identifier: String,
+}